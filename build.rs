@@ -0,0 +1,4 @@
+fn main() {
+    // Rebuild when a migration changes, so the embedded migrations stay current.
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -0,0 +1,9 @@
+-- Load checkpoints table - tracks the progress of every table load
+-- Used to resume an interrupted load after the last committed row
+CREATE TABLE load_checkpoints (
+    table_name TEXT PRIMARY KEY,              -- Table the XML file is loaded into
+    file_path TEXT NOT NULL,                  -- XML file the rows are read from
+    last_id INTEGER,                          -- Id of the last committed row, NULL before the first commit
+    completed BOOLEAN NOT NULL DEFAULT FALSE, -- Whether the whole XML file has been loaded
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP -- When the checkpoint was last written
+);
@@ -0,0 +1,8 @@
+-- Load dropped indexes table - holds the indexes dropped for a bulk load
+-- Used to recreate them after the load, even if it was interrupted
+CREATE TABLE load_dropped_indexes (
+    index_name TEXT PRIMARY KEY,           -- Name of the dropped index
+    table_name TEXT NOT NULL,              -- Table the index belongs to
+    definition TEXT NOT NULL,              -- CREATE INDEX statement, as returned by pg_get_indexdef
+    dropped_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP -- When the index was dropped
+);
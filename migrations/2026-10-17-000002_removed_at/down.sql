@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE votes DROP COLUMN IF EXISTS removed_at;
+ALTER TABLE users DROP COLUMN IF EXISTS removed_at;
+ALTER TABLE tags DROP COLUMN IF EXISTS removed_at;
+ALTER TABLE post_links DROP COLUMN IF EXISTS removed_at;
+ALTER TABLE post_historys DROP COLUMN IF EXISTS removed_at;
+ALTER TABLE posts DROP COLUMN IF EXISTS removed_at;
+ALTER TABLE comments DROP COLUMN IF EXISTS removed_at;
+ALTER TABLE badges DROP COLUMN IF EXISTS removed_at;
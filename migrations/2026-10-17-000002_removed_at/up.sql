@@ -0,0 +1,10 @@
+-- Removed at columns - soft-mark the rows that vanished from a newer dump
+-- Set by `load --removed-rows mark`, and cleared when a row comes back
+ALTER TABLE badges ADD COLUMN removed_at TIMESTAMP; -- When the row was found missing from the XML file
+ALTER TABLE comments ADD COLUMN removed_at TIMESTAMP; -- When the row was found missing from the XML file
+ALTER TABLE posts ADD COLUMN removed_at TIMESTAMP; -- When the row was found missing from the XML file
+ALTER TABLE post_historys ADD COLUMN removed_at TIMESTAMP; -- When the row was found missing from the XML file
+ALTER TABLE post_links ADD COLUMN removed_at TIMESTAMP; -- When the row was found missing from the XML file
+ALTER TABLE tags ADD COLUMN removed_at TIMESTAMP; -- When the row was found missing from the XML file
+ALTER TABLE users ADD COLUMN removed_at TIMESTAMP; -- When the row was found missing from the XML file
+ALTER TABLE votes ADD COLUMN removed_at TIMESTAMP; -- When the row was found missing from the XML file
@@ -0,0 +1,78 @@
+use diesel::{dsl::now, prelude::*, upsert::excluded};
+
+use crate::schema::load_checkpoints;
+
+#[derive(Clone, Debug, Queryable, Selectable)]
+#[diesel(table_name = load_checkpoints)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+/// Represents the progress of a table load, as last committed.
+///
+/// # Fields
+///
+/// * `table_name` - The table the XML file is loaded into
+/// * `file_path` - The XML file the rows are read from
+/// * `last_id` - The `Id` of the last committed row, if any
+/// * `completed` - Whether the whole XML file has been loaded
+pub(crate) struct Checkpoint {
+    pub table_name: String,
+    pub file_path: String,
+    pub last_id: Option<i32>,
+    pub completed: bool,
+}
+
+/// Reads the checkpoint of a table.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `table_name` - The table to read the checkpoint of
+///
+/// # Returns
+///
+/// * `Ok(Option<Checkpoint>)` - The checkpoint, or `None` if the table was never loaded
+/// * `Err(diesel::result::Error)` - If the query fails
+pub(crate) fn load(
+    connection: &mut PgConnection,
+    table_name: &str,
+) -> QueryResult<Option<Checkpoint>> {
+    load_checkpoints::table
+        .find(table_name)
+        .select(Checkpoint::as_select())
+        .first(connection)
+        .optional()
+}
+
+/// Records the progress of a table load.
+///
+/// This must run in the same transaction as the rows it accounts for, so the
+/// checkpoint never gets ahead of the data.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `checkpoint` - The progress to record
+///
+/// # Returns
+///
+/// * `Ok(())` - If the checkpoint was written
+/// * `Err(diesel::result::Error)` - If the upsert fails
+pub(crate) fn save(connection: &mut PgConnection, checkpoint: &Checkpoint) -> QueryResult<()> {
+    diesel::insert_into(load_checkpoints::table)
+        .values((
+            load_checkpoints::table_name.eq(&checkpoint.table_name),
+            load_checkpoints::file_path.eq(&checkpoint.file_path),
+            load_checkpoints::last_id.eq(checkpoint.last_id),
+            load_checkpoints::completed.eq(checkpoint.completed),
+        ))
+        .on_conflict(load_checkpoints::table_name)
+        .do_update()
+        .set((
+            load_checkpoints::file_path.eq(excluded(load_checkpoints::file_path)),
+            load_checkpoints::last_id.eq(excluded(load_checkpoints::last_id)),
+            load_checkpoints::completed.eq(excluded(load_checkpoints::completed)),
+            load_checkpoints::updated_at.eq(now),
+        ))
+        .execute(connection)?;
+
+    Ok(())
+}
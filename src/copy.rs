@@ -0,0 +1,398 @@
+use std::io::Write;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::pg::CopyFormat;
+
+use crate::errors::EtlError;
+use crate::model::Loadable;
+
+/// The signature that starts every binary `COPY` stream.
+const BINARY_SIGNATURE: &[u8; 11] = b"PGCOPY\n\xff\r\n\0";
+
+/// A value that can be written as a single column of a `COPY` row.
+///
+/// Implementations exist for every Rust type used by the models, and for
+/// `Option<T>`, where `None` is written as `NULL`.
+pub(crate) trait CopyValue {
+    /// Appends the text format representation of the value to `out`.
+    ///
+    /// Returns `false` if the value is `NULL` and nothing was written.
+    fn write_text(&self, out: &mut Vec<u8>) -> bool;
+
+    /// Appends the binary format representation of the value to `out`.
+    ///
+    /// Returns `false` if the value is `NULL` and nothing was written.
+    fn write_binary(&self, out: &mut Vec<u8>) -> bool;
+}
+
+impl CopyValue for i16 {
+    fn write_text(&self, out: &mut Vec<u8>) -> bool {
+        out.extend_from_slice(self.to_string().as_bytes());
+        true
+    }
+
+    fn write_binary(&self, out: &mut Vec<u8>) -> bool {
+        out.extend_from_slice(&self.to_be_bytes());
+        true
+    }
+}
+
+impl CopyValue for i32 {
+    fn write_text(&self, out: &mut Vec<u8>) -> bool {
+        out.extend_from_slice(self.to_string().as_bytes());
+        true
+    }
+
+    fn write_binary(&self, out: &mut Vec<u8>) -> bool {
+        out.extend_from_slice(&self.to_be_bytes());
+        true
+    }
+}
+
+impl CopyValue for bool {
+    fn write_text(&self, out: &mut Vec<u8>) -> bool {
+        out.push(if *self { b't' } else { b'f' });
+        true
+    }
+
+    fn write_binary(&self, out: &mut Vec<u8>) -> bool {
+        out.push(u8::from(*self));
+        true
+    }
+}
+
+impl CopyValue for String {
+    fn write_text(&self, out: &mut Vec<u8>) -> bool {
+        // Backslash, tab and line breaks are significant in the text format.
+        // The other control characters are escaped like PostgreSQL does on
+        // `COPY ... TO`, so rows read back from a table compare equal.
+        for byte in self.bytes() {
+            match byte {
+                b'\\' => out.extend_from_slice(b"\\\\"),
+                b'\t' => out.extend_from_slice(b"\\t"),
+                b'\n' => out.extend_from_slice(b"\\n"),
+                b'\r' => out.extend_from_slice(b"\\r"),
+                b'\x08' => out.extend_from_slice(b"\\b"),
+                b'\x0b' => out.extend_from_slice(b"\\v"),
+                b'\x0c' => out.extend_from_slice(b"\\f"),
+                _ => out.push(byte),
+            }
+        }
+        true
+    }
+
+    fn write_binary(&self, out: &mut Vec<u8>) -> bool {
+        out.extend_from_slice(self.as_bytes());
+        true
+    }
+}
+
+impl CopyValue for NaiveDateTime {
+    fn write_text(&self, out: &mut Vec<u8>) -> bool {
+        // Written like PostgreSQL does, with the microseconds but without
+        // their trailing zeros.
+        out.extend_from_slice(self.format("%Y-%m-%d %H:%M:%S").to_string().as_bytes());
+
+        let microseconds = format!("{:06}", self.and_utc().timestamp_subsec_micros());
+        let microseconds = microseconds.trim_end_matches('0');
+
+        if !microseconds.is_empty() {
+            out.push(b'.');
+            out.extend_from_slice(microseconds.as_bytes());
+        }
+
+        true
+    }
+
+    fn write_binary(&self, out: &mut Vec<u8>) -> bool {
+        // PostgreSQL timestamps are microseconds since 2000-01-01 00:00:00.
+        let epoch = NaiveDate::from_ymd_opt(2000, 1, 1)
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .expect("the PostgreSQL epoch is a valid date");
+        let microseconds = self
+            .signed_duration_since(epoch)
+            .num_microseconds()
+            .unwrap_or(i64::MAX);
+
+        out.extend_from_slice(&microseconds.to_be_bytes());
+        true
+    }
+}
+
+impl<T> CopyValue for Option<T>
+where
+    T: CopyValue,
+{
+    fn write_text(&self, out: &mut Vec<u8>) -> bool {
+        self.as_ref().is_some_and(|value| value.write_text(out))
+    }
+
+    fn write_binary(&self, out: &mut Vec<u8>) -> bool {
+        self.as_ref().is_some_and(|value| value.write_binary(out))
+    }
+}
+
+/// A buffer holding a single row encoded in a `COPY` format.
+///
+/// Models push their columns with [`CopyRow::field`] in the same order as
+/// the columns of their table in `schema.rs`.
+pub(crate) struct CopyRow {
+    format: CopyFormat,
+    buffer: Vec<u8>,
+    columns: i16,
+}
+
+impl CopyRow {
+    /// Creates an empty row for the given format.
+    pub(crate) fn new(format: CopyFormat) -> Self {
+        Self {
+            format,
+            buffer: Vec::new(),
+            columns: 0,
+        }
+    }
+
+    /// Resets the row so the buffer can be reused for the next model.
+    fn clear(&mut self) {
+        self.buffer.clear();
+        self.columns = 0;
+
+        if let CopyFormat::Binary = self.format {
+            // Placeholder for the column count, filled in by `finish`.
+            self.buffer.extend_from_slice(&0i16.to_be_bytes());
+        }
+    }
+
+    /// Appends the next column of the row.
+    pub(crate) fn field<V>(&mut self, value: &V)
+    where
+        V: CopyValue,
+    {
+        match self.format {
+            CopyFormat::Binary => {
+                // Every field is prefixed with its length, or -1 for NULL.
+                let start = self.buffer.len();
+                self.buffer.extend_from_slice(&(-1i32).to_be_bytes());
+
+                if value.write_binary(&mut self.buffer) {
+                    let length = (self.buffer.len() - start - 4) as i32;
+                    self.buffer[start..start + 4].copy_from_slice(&length.to_be_bytes());
+                }
+            }
+            _ => {
+                if self.columns > 0 {
+                    self.buffer.push(b'\t');
+                }
+
+                if !value.write_text(&mut self.buffer) {
+                    self.buffer.extend_from_slice(b"\\N");
+                }
+            }
+        }
+
+        self.columns += 1;
+    }
+
+    /// Terminates the row and returns the encoded bytes.
+    fn finish(&mut self) -> &[u8] {
+        match self.format {
+            CopyFormat::Binary => self.buffer[..2].copy_from_slice(&self.columns.to_be_bytes()),
+            _ => self.buffer.push(b'\n'),
+        }
+
+        &self.buffer
+    }
+
+    /// Encodes the columns of a model, reusing the buffer of the row.
+    ///
+    /// # Parameters
+    ///
+    /// * `model` - The model to encode
+    ///
+    /// # Returns
+    ///
+    /// * `&[u8]` - The encoded row, terminated like in a `COPY` stream
+    pub(crate) fn encode<T>(&mut self, model: &T) -> &[u8]
+    where
+        T: Loadable,
+    {
+        self.clear();
+        model.write_copy_row(self);
+        self.finish()
+    }
+}
+
+/// Streams every model of `rows` into `out` in the given format.
+///
+/// # Errors
+///
+/// Returns an error if writing to the `COPY` stream fails.
+fn write_rows<T>(rows: &[T], format: CopyFormat, out: &mut dyn Write) -> Result<(), EtlError>
+where
+    T: Loadable,
+{
+    if let CopyFormat::Binary = format {
+        // Signature, flags field and header extension length.
+        out.write_all(BINARY_SIGNATURE)?;
+        out.write_all(&0i32.to_be_bytes())?;
+        out.write_all(&0i32.to_be_bytes())?;
+    }
+
+    let mut row = CopyRow::new(format);
+
+    for model in rows {
+        out.write_all(row.encode(model))?;
+    }
+
+    if let CopyFormat::Binary = format {
+        // File trailer.
+        out.write_all(&(-1i16).to_be_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Loads every model of `rows` with a single `COPY ... FROM STDIN`.
+///
+/// # Arguments
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `rows` - The parsed models
+/// * `format` - The `COPY` format to stream the rows in
+///
+/// # Returns
+///
+/// * `Result<usize, EtlError>` - The number of rows copied if successful
+///
+/// # Errors
+///
+/// Returns an error if the `COPY` statement fails or the stream cannot be written.
+pub(crate) fn copy_rows<T>(
+    connection: &mut diesel::PgConnection,
+    rows: &[T],
+    format: CopyFormat,
+) -> Result<usize, EtlError>
+where
+    T: Loadable,
+{
+    T::copy_from(connection, format, |out| write_rows(rows, format, out))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveDateTime};
+    use diesel::pg::CopyFormat;
+
+    use super::{BINARY_SIGNATURE, CopyRow, write_rows};
+    use crate::model::Badge;
+
+    fn date_time(micros: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2010, 7, 20)
+            .and_then(|date| date.and_hms_micro_opt(19, 7, 22, micros))
+            .unwrap()
+    }
+
+    fn badge() -> Badge {
+        Badge {
+            id: 7,
+            user_id: 42,
+            name: "Tag\tEditor".to_string(),
+            date: date_time(990_000),
+            class: 3,
+            tag_based: true,
+        }
+    }
+
+    #[test]
+    fn text_row_separates_the_columns() {
+        let mut row = CopyRow::new(CopyFormat::Text);
+
+        assert_eq!(
+            row.encode(&badge()),
+            b"7\t42\tTag\\tEditor\t2010-07-20 19:07:22.99\t3\tt\n"
+        );
+    }
+
+    #[test]
+    fn text_row_escapes_strings() {
+        let mut row = CopyRow::new(CopyFormat::Text);
+        row.clear();
+        row.field(&"a\\b\tc\nd\re\x08f\x0bg\x0ch é".to_string());
+
+        assert_eq!(row.finish(), b"a\\\\b\\tc\\nd\\re\\bf\\vg\\fh \xc3\xa9\n");
+    }
+
+    #[test]
+    fn text_row_writes_null() {
+        let mut row = CopyRow::new(CopyFormat::Text);
+        row.clear();
+        row.field(&1);
+        row.field(&None::<String>);
+        row.field(&Some(false));
+
+        assert_eq!(row.finish(), b"1\t\\N\tf\n");
+    }
+
+    #[test]
+    fn text_row_trims_the_microseconds() {
+        let mut row = CopyRow::new(CopyFormat::Text);
+        row.clear();
+        row.field(&date_time(0));
+        row.field(&date_time(1));
+        row.field(&date_time(120_000));
+
+        assert_eq!(
+            row.finish(),
+            b"2010-07-20 19:07:22\t2010-07-20 19:07:22.000001\t2010-07-20 19:07:22.12\n"
+        );
+    }
+
+    #[test]
+    fn binary_row_prefixes_the_columns_with_their_length() {
+        let mut row = CopyRow::new(CopyFormat::Binary);
+        row.clear();
+        row.field(&1i16);
+        row.field(&-2i32);
+        row.field(&"é".to_string());
+        row.field(&None::<i32>);
+        row.field(&true);
+
+        let expected: &[u8] = &[
+            0, 5, // columns
+            0, 0, 0, 2, 0, 1, // i16
+            0, 0, 0, 4, 0xff, 0xff, 0xff, 0xfe, // i32
+            0, 0, 0, 2, 0xc3, 0xa9, // text
+            0xff, 0xff, 0xff, 0xff, // NULL
+            0, 0, 0, 1, 1, // bool
+        ];
+        assert_eq!(row.finish(), expected);
+    }
+
+    #[test]
+    fn binary_row_counts_timestamps_from_2000() {
+        let epoch = NaiveDate::from_ymd_opt(2000, 1, 1)
+            .and_then(|date| date.and_hms_micro_opt(0, 0, 1, 5))
+            .unwrap();
+        let mut row = CopyRow::new(CopyFormat::Binary);
+        row.clear();
+        row.field(&epoch);
+
+        let mut expected = vec![0, 1, 0, 0, 0, 8];
+        expected.extend_from_slice(&1_000_005i64.to_be_bytes());
+        assert_eq!(row.finish(), expected);
+    }
+
+    #[test]
+    fn binary_stream_has_a_header_and_a_trailer() {
+        let mut out = Vec::new();
+        write_rows(&[badge(), badge()], CopyFormat::Binary, &mut out).unwrap();
+
+        let row = CopyRow::new(CopyFormat::Binary).encode(&badge()).to_vec();
+        let mut expected = BINARY_SIGNATURE.to_vec();
+        expected.extend_from_slice(&[0; 8]);
+        expected.extend_from_slice(&row);
+        expected.extend_from_slice(&row);
+        expected.extend_from_slice(&[0xff, 0xff]);
+        assert_eq!(out, expected);
+    }
+}
@@ -0,0 +1,82 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use diesel::{pg::CopyFormat, prelude::*};
+
+use crate::{errors::EtlError, model::Loadable};
+
+/// Returns the path of the file a table is exported to.
+///
+/// # Parameters
+///
+/// * `output_dir` - The directory holding the exported files
+/// * `table` - The name of the table
+/// * `format` - The format the table is exported in
+///
+/// # Returns
+///
+/// * `PathBuf` - `{table}.csv` in the CSV format, `{table}.tsv` in the text format
+pub(crate) fn export_path(output_dir: &Path, table: &str, format: CopyFormat) -> PathBuf {
+    let extension = match format {
+        CopyFormat::Csv => "csv",
+        _ => "tsv",
+    };
+
+    output_dir.join(format!("{}.{}", table, extension))
+}
+
+/// Copies every row of the table of the model `T` to a file, including the
+/// rows marked as removed.
+///
+/// The rows are written to a `.partial` file first, which is renamed once
+/// the `COPY` completed, so an interrupted export leaves no truncated file
+/// behind under the final name.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `path` - The file to write, replaced if it exists
+/// * `format` - The `COPY` format of the file, the CSV format starting with a header line
+///
+/// # Returns
+///
+/// * `Ok(u64)` - The number of bytes written
+/// * `Err(EtlError)` - If the table cannot be read or the file cannot be written
+pub(crate) fn export_table<T>(
+    connection: &mut PgConnection,
+    path: &Path,
+    format: CopyFormat,
+) -> Result<u64, EtlError>
+where
+    T: Loadable,
+{
+    let mut partial_path = path.as_os_str().to_owned();
+    partial_path.push(".partial");
+    let partial_path = PathBuf::from(partial_path);
+
+    let mut out = BufWriter::new(File::create(&partial_path)?);
+    let mut bytes = 0;
+
+    if let CopyFormat::Csv = format {
+        // Older PostgreSQL versions only accept `HEADER` in the CSV format,
+        // so the header is written from the column names.
+        let header = format!("{},removed_at\n", T::COLUMN_NAMES.join(","));
+        out.write_all(header.as_bytes())?;
+        bytes += header.len() as u64;
+    }
+
+    T::copy_to(connection, format, |rows| {
+        bytes += std::io::copy(rows, &mut out)?;
+        Ok(())
+    })?;
+
+    out.into_inner()
+        .map_err(|error| error.into_error())?
+        .sync_all()?;
+    std::fs::rename(&partial_path, path)?;
+
+    Ok(bytes)
+}
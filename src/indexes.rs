@@ -0,0 +1,158 @@
+use diesel::{
+    prelude::*,
+    sql_query,
+    sql_types::{Bool, Text},
+};
+
+use crate::schema::load_dropped_indexes;
+
+#[derive(Clone, Debug, Queryable, QueryableByName, Selectable)]
+#[diesel(table_name = load_dropped_indexes)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+/// Represents a secondary index of a table, as recorded before dropping it.
+///
+/// # Fields
+///
+/// * `index_name` - The name of the index
+/// * `definition` - The `CREATE INDEX` statement recreating the index
+pub(crate) struct IndexDefinition {
+    #[diesel(sql_type = Text)]
+    pub index_name: String,
+    #[diesel(sql_type = Text)]
+    pub definition: String,
+}
+
+#[derive(Debug, QueryableByName)]
+/// Represents whether an existing index can be used.
+struct IndexState {
+    #[diesel(sql_type = Bool)]
+    valid: bool,
+}
+
+/// Records and drops the secondary indexes of a table.
+///
+/// Indexes backing a constraint, like the primary key, are kept. The
+/// definitions are recorded in `load_dropped_indexes` in the same transaction
+/// as the drops, so an interrupted load never loses an index. An index
+/// still recorded by an earlier load, like an invalid index left by a failed
+/// `CREATE INDEX CONCURRENTLY`, keeps its recorded definition.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `table_name` - The table to drop the indexes of
+///
+/// # Returns
+///
+/// * `Ok(Vec<IndexDefinition>)` - The indexes that were dropped
+/// * `Err(diesel::result::Error)` - If a query fails
+pub(crate) fn drop_indexes(
+    connection: &mut PgConnection,
+    table_name: &str,
+) -> QueryResult<Vec<IndexDefinition>> {
+    connection.transaction(|connection| {
+        let indexes = sql_query(
+            "SELECT index_class.relname AS index_name, \
+             pg_get_indexdef(pg_index.indexrelid) AS definition \
+             FROM pg_index \
+             JOIN pg_class AS index_class ON index_class.oid = pg_index.indexrelid \
+             WHERE pg_index.indrelid = to_regclass($1) \
+             AND NOT EXISTS (SELECT 1 FROM pg_constraint WHERE conindid = pg_index.indexrelid) \
+             ORDER BY index_class.relname",
+        )
+        .bind::<Text, _>(table_name)
+        .load::<IndexDefinition>(connection)?;
+
+        for index in &indexes {
+            diesel::insert_into(load_dropped_indexes::table)
+                .values((
+                    load_dropped_indexes::index_name.eq(&index.index_name),
+                    load_dropped_indexes::table_name.eq(table_name),
+                    load_dropped_indexes::definition.eq(&index.definition),
+                ))
+                .on_conflict_do_nothing()
+                .execute(connection)?;
+
+            sql_query(format!("DROP INDEX \"{}\"", index.index_name)).execute(connection)?;
+        }
+
+        Ok(indexes)
+    })
+}
+
+/// Lists the recorded indexes of a table that still have to be recreated.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `table_name` - The table to list the dropped indexes of
+///
+/// # Returns
+///
+/// * `Ok(Vec<IndexDefinition>)` - The dropped indexes, possibly none
+/// * `Err(diesel::result::Error)` - If the query fails
+pub(crate) fn dropped_indexes(
+    connection: &mut PgConnection,
+    table_name: &str,
+) -> QueryResult<Vec<IndexDefinition>> {
+    load_dropped_indexes::table
+        .filter(load_dropped_indexes::table_name.eq(table_name))
+        .order(load_dropped_indexes::index_name)
+        .select(IndexDefinition::as_select())
+        .load(connection)
+}
+
+/// Recreates a dropped index and forgets its definition.
+///
+/// A failed `CREATE INDEX CONCURRENTLY` leaves an invalid index behind,
+/// which is dropped before building the index again. A valid index of the
+/// same name was built by a run interrupted before forgetting it, and is
+/// kept.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `index` - The index to recreate
+/// * `concurrently` - Whether to build the index with `CREATE INDEX CONCURRENTLY`
+///
+/// # Returns
+///
+/// * `Ok(())` - If the index was created
+/// * `Err(diesel::result::Error)` - If the index cannot be built
+pub(crate) fn create_index(
+    connection: &mut PgConnection,
+    index: &IndexDefinition,
+    concurrently: bool,
+) -> QueryResult<()> {
+    let definition = match concurrently {
+        // `CONCURRENTLY` goes right after `INDEX` in every definition
+        // returned by `pg_get_indexdef`.
+        true => index
+            .definition
+            .replacen(" INDEX ", " INDEX CONCURRENTLY ", 1),
+        false => index.definition.clone(),
+    };
+
+    let existing = sql_query(
+        "SELECT indisvalid AS valid FROM pg_index WHERE indexrelid = to_regclass(quote_ident($1))",
+    )
+    .bind::<Text, _>(&index.index_name)
+    .get_result::<IndexState>(connection)
+    .optional()?;
+
+    match existing {
+        Some(IndexState { valid: true }) => {}
+        existing => {
+            if existing.is_some() {
+                sql_query(format!("DROP INDEX \"{}\"", index.index_name)).execute(connection)?;
+            }
+
+            // `CREATE INDEX CONCURRENTLY` cannot run inside a transaction block.
+            sql_query(definition).execute(connection)?;
+        }
+    }
+
+    diesel::delete(load_dropped_indexes::table.find(&index.index_name)).execute(connection)?;
+
+    Ok(())
+}
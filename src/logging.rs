@@ -0,0 +1,34 @@
+use std::io::{self, IsTerminal};
+
+use tracing::Level;
+
+use crate::progress;
+
+/// Logs the messages of the given level and above on standard error.
+///
+/// # Parameters
+///
+/// * `level` - The least severe level logged
+/// * `json` - Whether every message is logged as a JSON object instead of text
+pub(crate) fn init(level: Level, json: bool) {
+    // Erase the progress bar before every message, the next tick redraws it.
+    let writer = || {
+        progress::clear_bar();
+        io::stderr()
+    };
+
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_target(false)
+        .with_ansi(io::stderr().is_terminal())
+        .with_writer(writer);
+
+    match json {
+        true => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .init(),
+        false => builder.init(),
+    }
+}
@@ -1,481 +1,2479 @@
+mod checkpoint;
+mod copy;
 mod errors;
+mod export;
+mod indexes;
+mod logging;
+mod migrations;
 mod model;
+mod progress;
+mod references;
+mod rejects;
+mod removed;
+mod report;
 mod schema;
+mod source;
+mod staging;
+mod stats;
+mod upsert;
+mod verify;
+mod views;
 
-use clap::Parser;
-use diesel::prelude::*;
-use model::{Badge, Comment, Post, PostHistory, PostLink, Tag, User, Vote, XmlModelIterator};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use diesel::{
+    pg::CopyFormat,
+    prelude::*,
+    r2d2::{ConnectionManager, ManageConnection, Pool},
+    result::{DatabaseErrorInformation, DatabaseErrorKind},
+};
+use errors::{Context, EtlError};
+use model::{
+    Badge, Comment, Loadable, ParallelXmlModelIterator, Post, PostHistory, PostLink, RowError, Tag,
+    User, Vote, XmlModelIterator,
+};
+use progress::Progress;
+use rejects::{Rejects, TableRejects};
+use removed::IdSet;
+use report::Report;
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    io::{self, IsTerminal},
+    num::{NonZeroU64, NonZeroUsize},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    slice,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::{Level, Span, error, info, info_span, warn};
+
+/// The number of rows per `COPY` statement in the copy modes, kept in memory
+/// to insert them one at a time if one of them breaks a constraint.
+const COPY_BATCH_SIZE: usize = 10000;
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+/// How rows are written to the database.
+enum LoadMode {
+    /// Multi-row `INSERT` statements of `--batch-size` rows.
+    Insert,
+
+    /// `COPY ... FROM STDIN` statements of `COPY_BATCH_SIZE` rows, in text
+    /// format.
+    CopyText,
+
+    /// `COPY ... FROM STDIN` statements of `COPY_BATCH_SIZE` rows, in binary
+    /// format.
+    #[default]
+    CopyBinary,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+/// What happens to rows whose `Id` is already in their table.
+enum OnConflict {
+    /// Fail the load of the table.
+    #[default]
+    Error,
+
+    /// Keep the existing row.
+    Skip,
+
+    /// Update the existing row with the values of the XML file.
+    Update,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+/// What happens to rows of a table that are not in its XML file.
+enum RemovedRows {
+    /// Keep the rows without looking for them.
+    #[default]
+    Keep,
+
+    /// Only report the number of rows.
+    Count,
+
+    /// Delete the rows.
+    Delete,
+
+    /// Set the `removed_at` column of the rows.
+    Mark,
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+/// The format of the files written by `export`.
+enum ExportFormat {
+    /// Comma-separated values with a header line, in `.csv` files.
+    #[default]
+    Csv,
+
+    /// The tab-separated text format of `COPY`, in `.tsv` files.
+    Text,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+/// What happens to the rows referencing an `id` missing from another table.
+enum OrphanPolicy {
+    /// Only count them.
+    #[default]
+    Keep,
+
+    /// Set their references to `NULL` where the column is nullable, and keep
+    /// the other ones.
+    Nullify,
+
+    /// Set their references to `NULL` where the column is nullable, and
+    /// delete the rows with another orphan reference.
+    Delete,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+/// How the progress of the tables being loaded is reported.
+enum ProgressMode {
+    /// A progress bar if standard error is a terminal, log lines otherwise.
+    #[default]
+    Auto,
+
+    /// A progress bar redrawn in place.
+    Bar,
+
+    /// A line per table every `--progress-interval` seconds.
+    Log,
+
+    /// No progress.
+    Off,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+/// The least severe messages logged.
+enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+/// How messages are logged on standard error.
+enum LogFormat {
+    /// A line of text per message, with its fields as `key=value`.
+    #[default]
+    Text,
+
+    /// A JSON object per line, for log aggregation.
+    Json,
+}
 
 #[derive(Clone, Debug, Parser)]
 #[command(version, about, long_about = None)]
 /// A command-line tool to load StackExchange XML dumps into a
 /// PostgreSQL database.
 struct Arguments {
-    /// The path to the badges XML file.
+    #[command(subcommand)]
+    command: Command,
+
+    /// The least severe messages logged.
+    // Listed after the options of the subcommands, which are numbered from 0.
+    #[arg(long, value_enum, default_value_t, global = true, display_order = 100)]
+    log_level: LogLevel,
+
+    /// How messages are logged on standard error. With `json`, `--progress
+    /// auto` logs progress messages instead of drawing a bar.
+    #[arg(long, value_enum, default_value_t, global = true, display_order = 101)]
+    log_format: LogFormat,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+/// The actions the tool can run.
+enum Command {
+    /// Load the XML files of a dump into the database.
+    ///
+    /// Only the tables whose XML file is given are loaded.
+    Load(Box<LoadArguments>),
+
+    /// Create or upgrade the database schema with the embedded migrations.
+    ///
+    /// The pending migrations are applied, then every applied version is
+    /// listed.
+    Migrate(MigrateArguments),
+
+    /// Report the rows, `Id` range, size and load progress of every table.
+    Stats(StatsArguments),
+
+    /// Refresh the materialized views, such as `post_stats`.
+    ///
+    /// Views with a unique index are refreshed concurrently, so they can be
+    /// queried during the refresh.
+    RefreshViews(RefreshViewsArguments),
+
+    /// Compare the XML files of a dump with the loaded tables.
+    ///
+    /// The number of rows and the `Id` range of every table, and optionally
+    /// a checksum of every column, must match its XML file.
+    Verify(Box<VerifyArguments>),
+
+    /// Count the rows referencing an `id` missing from another table, like
+    /// votes on deleted posts.
+    ///
+    /// The orphans can be set to `NULL` or deleted, and the references
+    /// enforced with `NOT VALID` foreign keys, in a single transaction.
+    CheckReferences(CheckReferencesArguments),
+
+    /// Copy the tables to CSV or text files, one per table.
+    ///
+    /// Every column is exported, including `removed_at`, so the rows marked
+    /// as removed can be told apart.
+    Export(ExportArguments),
+}
+
+#[derive(Clone, Debug, Args)]
+#[group(id = "inputs", required = true, multiple = true)]
+/// The XML files of a dump, read by the `load` and `verify` subcommands.
+struct InputArguments {
+    /// The path to a dump directory holding `Badges.xml`, `Comments.xml`,
+    /// `Posts.xml`, etc., possibly compressed. File names are matched
+    /// case-insensitively, and the `--*-path` options override individual
+    /// files.
+    #[arg(long)]
+    dump_dir: Option<PathBuf>,
+
+    /// The path to the badges XML file, or `-` for standard input.
+    #[arg(long)]
+    badges_path: Option<PathBuf>,
+
+    /// The path to the comments XML file, or `-` for standard input.
+    #[arg(long)]
+    comments_path: Option<PathBuf>,
+
+    /// The path to the posts XML file, or `-` for standard input.
+    #[arg(long)]
+    posts_path: Option<PathBuf>,
+
+    /// The path to the post history XML file, or `-` for standard input.
+    #[arg(long)]
+    post_history_path: Option<PathBuf>,
+
+    /// The path to the post links XML file, or `-` for standard input.
+    #[arg(long)]
+    post_links_path: Option<PathBuf>,
+
+    /// The path to the tags XML file, or `-` for standard input.
+    #[arg(long)]
+    tags_path: Option<PathBuf>,
+
+    /// The path to the users XML file, or `-` for standard input.
+    #[arg(long)]
+    users_path: Option<PathBuf>,
+
+    /// The path to the votes XML file, or `-` for standard input.
+    #[arg(long)]
+    votes_path: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Args)]
+/// The arguments of the `load` subcommand.
+struct LoadArguments {
+    #[command(flatten)]
+    inputs: InputArguments,
+
+    /// The database URL.
     #[arg(long)]
-    badges_path: std::path::PathBuf,
+    database_url: String,
+
+    /// The number of tables loaded concurrently, each on its own database
+    /// connection. Defaults to the number of CPUs.
+    #[arg(long)]
+    jobs: Option<NonZeroUsize>,
+
+    /// Run the pending migrations before loading.
+    #[arg(long)]
+    auto_migrate: bool,
+
+    /// Do not refresh the materialized views once every table is loaded.
+    #[arg(long)]
+    skip_refresh_views: bool,
+
+    /// Write the rows that cannot be parsed to this file, as JSON Lines
+    /// giving the file, byte offset, `Id`, attribute and raw value of every
+    /// row. The file is replaced, unless `--resume` appends to it.
+    #[arg(long, value_name = "PATH")]
+    rejects_path: Option<PathBuf>,
+
+    /// Write a JSON summary of the load to this file, giving the size and
+    /// SHA-256 checksum of every XML file, the rows read, inserted and
+    /// rejected by reason, the elapsed time and throughput of every table.
+    /// The file is replaced.
+    #[arg(long, value_name = "PATH")]
+    report_path: Option<PathBuf>,
+
+    /// How the rows and bytes read, throughput and estimated time left of
+    /// every table are reported, on standard error.
+    #[arg(long, value_enum, default_value_t)]
+    progress: ProgressMode,
+
+    /// The number of seconds between two progress lines of a table with
+    /// `--progress log`.
+    #[arg(long, value_name = "SECONDS", default_value = "10")]
+    progress_interval: NonZeroU64,
+
+    #[command(flatten)]
+    load_options: LoadOptions,
+}
+
+#[derive(Clone, Debug, Args)]
+/// The arguments of the `migrate` subcommand.
+struct MigrateArguments {
+    /// The database URL.
+    #[arg(long)]
+    database_url: String,
 
-    /// The path to the comments XML file.
+    /// Only list the applied and pending migrations, without running them.
     #[arg(long)]
-    comments_path: std::path::PathBuf,
+    list: bool,
+}
 
-    /// The path to the posts XML file.
+#[derive(Clone, Debug, Args)]
+/// The arguments of the `stats` subcommand.
+struct StatsArguments {
+    /// The database URL.
     #[arg(long)]
-    posts_path: std::path::PathBuf,
+    database_url: String,
+}
 
-    /// The path to the post history XML file.
+#[derive(Clone, Debug, Args)]
+/// The arguments of the `refresh-views` subcommand.
+struct RefreshViewsArguments {
+    /// The database URL.
     #[arg(long)]
-    post_history_path: std::path::PathBuf,
+    database_url: String,
+}
 
-    /// The path to the post links XML file.
+#[derive(Clone, Debug, Args)]
+/// The arguments of the `verify` subcommand.
+struct VerifyArguments {
+    #[command(flatten)]
+    inputs: InputArguments,
+
+    /// The database URL.
     #[arg(long)]
-    post_links_path: std::path::PathBuf,
+    database_url: String,
 
-    /// The path to the tags XML file.
+    /// Also compare a checksum of every column, reading every row of the
+    /// tables.
     #[arg(long)]
-    tags_path: std::path::PathBuf,
+    checksums: bool,
+
+    /// The number of threads parsing the rows of each XML file.
+    #[arg(long, default_value = "1")]
+    parse_threads: NonZeroUsize,
+}
 
-    /// The path to the users XML file.
+#[derive(Clone, Debug, Args)]
+/// The arguments of the `check-references` subcommand.
+struct CheckReferencesArguments {
+    /// The database URL.
     #[arg(long)]
-    users_path: std::path::PathBuf,
+    database_url: String,
+
+    /// What happens to the rows referencing an `id` missing from another
+    /// table.
+    #[arg(long, value_enum, default_value_t)]
+    orphans: OrphanPolicy,
 
-    /// The path to the votes XML file.
+    /// Add a `NOT VALID` foreign key for every reference that has none, so
+    /// new rows are checked but the existing ones are not.
     #[arg(long)]
-    votes_path: std::path::PathBuf,
+    add_foreign_keys: bool,
 
+    /// Drop the foreign keys added by `--add-foreign-keys`, e.g. before
+    /// loading a newer dump.
+    #[arg(long, conflicts_with = "add_foreign_keys")]
+    drop_foreign_keys: bool,
+}
+
+#[derive(Clone, Debug, Args)]
+/// The arguments of the `export` subcommand.
+struct ExportArguments {
     /// The database URL.
     #[arg(long)]
     database_url: String,
+
+    /// The directory the files are written to, created if missing. Existing
+    /// files of the exported tables are replaced.
+    #[arg(long)]
+    output_dir: PathBuf,
+
+    /// The format of the files.
+    #[arg(long, value_enum, default_value_t)]
+    format: ExportFormat,
+
+    /// The tables to export, separated by commas. Every table is exported by
+    /// default.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_parser = clap::builder::PossibleValuesParser::new(model::TABLE_NAMES)
+    )]
+    tables: Vec<String>,
+}
+
+#[derive(Clone, Debug, Args)]
+/// Options controlling how the rows of every table are written.
+struct LoadOptions {
+    /// How rows are written to the database.
+    #[arg(long, value_enum, default_value_t)]
+    load_mode: LoadMode,
+
+    /// What happens to rows whose `Id` is already in their table, e.g.
+    /// when loading a newer dump into a populated database.
+    #[arg(long, value_enum, default_value_t)]
+    on_conflict: OnConflict,
+
+    /// What happens to the rows of a table that are not in its XML file
+    /// anymore, once the whole file is loaded. Use `count` for a dry run.
+    #[arg(long, value_enum, default_value_t)]
+    removed_rows: RemovedRows,
+
+    /// The number of rows per `INSERT` statement in insert mode, capped
+    /// per table to stay within PostgreSQL's bind parameter limit.
+    #[arg(long, default_value = "1000")]
+    batch_size: NonZeroUsize,
+
+    /// Fail a table once more than this many of its rows are rejected,
+    /// because they cannot be parsed or break a constraint of the table.
+    /// Unlimited by default.
+    #[arg(long)]
+    max_errors: Option<usize>,
+
+    /// Fail a table once more than this percentage of its rows are
+    /// rejected, checked after its first 1000 rows and at the end of its
+    /// XML file. Unlimited by default.
+    #[arg(long, value_name = "PERCENT", value_parser = parse_percentage)]
+    max_error_rate: Option<f64>,
+
+    /// The number of threads parsing the rows of each XML file. With more
+    /// than one, a reader thread splits the file into chunks of rows that
+    /// are parsed concurrently.
+    #[arg(long, default_value = "1")]
+    parse_threads: NonZeroUsize,
+
+    /// Commit after every N rows instead of once per table.
+    #[arg(long)]
+    commit_interval: Option<NonZeroUsize>,
+
+    /// Resume interrupted loads after the last committed row, and skip the
+    /// tables that were completely loaded.
+    #[arg(long)]
+    resume: bool,
+
+    /// Load every table into an `UNLOGGED` copy in the `staging` schema,
+    /// and swap the copies in place of the live tables in a single
+    /// transaction once they are all loaded and indexed.
+    #[arg(long, conflicts_with = "resume")]
+    staging: bool,
+
+    /// Drop the secondary indexes of every table before loading it, and
+    /// recreate them once it is loaded.
+    #[arg(long)]
+    rebuild_indexes: bool,
+
+    /// The number of indexes of a table recreated concurrently with
+    /// `--rebuild-indexes`, each on its own database connection. Ignored
+    /// with `--create-indexes-concurrently`.
+    #[arg(long, default_value = "1")]
+    index_jobs: NonZeroUsize,
+
+    /// Recreate the indexes with `CREATE INDEX CONCURRENTLY`, which does not
+    /// block writes to the table but takes longer. The indexes of a table
+    /// are then built one at a time.
+    #[arg(long)]
+    create_indexes_concurrently: bool,
+}
+
+/// Returns the XML file name a dump file stands for, in lowercase
+///
+/// Compressed files are matched by the name of the XML file they hold, e.g.
+/// `Posts.xml.gz`, `Posts.7z` or `stackoverflow.com-Posts.7z` for
+/// `posts.xml`.
+///
+/// # Arguments
+///
+/// * `file_name` - The name of a file of the dump directory
+///
+/// # Returns
+///
+/// * `(String, bool)` - The XML file name, and whether the file is compressed
+fn dump_file_key(file_name: &str) -> (String, bool) {
+    let file_name = file_name.to_lowercase();
+
+    let Some(archive_name) = [".gz", ".zst", ".bz2", ".7z"]
+        .iter()
+        .find_map(|extension| file_name.strip_suffix(extension))
+    else {
+        return (file_name, false);
+    };
+
+    // Archives of a single table are named after their site, e.g.
+    // `stackoverflow.com-Posts.7z`.
+    let stem = archive_name.strip_suffix(".xml").unwrap_or(archive_name);
+    let xml_name = stem.rsplit('-').next().unwrap_or(stem);
+
+    (format!("{}.xml", xml_name), true)
+}
+
+/// Lists the files of a dump directory, keyed by the lowercase name of the
+/// XML file they hold
+///
+/// Uncompressed files take precedence over compressed ones.
+///
+/// # Arguments
+///
+/// * `dump_dir` - Path to the dump directory
+///
+/// # Returns
+///
+/// * `std::io::Result<HashMap<String, PathBuf>>` - The files of the directory if successful
+///
+/// # Errors
+///
+/// Returns an error if the directory cannot be read
+fn find_dump_files(dump_dir: &Path) -> std::io::Result<HashMap<String, PathBuf>> {
+    let mut dump_files = HashMap::new();
+
+    for entry in std::fs::read_dir(dump_dir)? {
+        let path = entry?.path();
+
+        if let Some(file_name) = path.file_name().and_then(|name| name.to_str())
+            && path.is_file()
+        {
+            match dump_file_key(file_name) {
+                (xml_name, true) => {
+                    dump_files.entry(xml_name).or_insert(path);
+                }
+                (xml_name, false) => {
+                    dump_files.insert(xml_name, path);
+                }
+            }
+        }
+    }
+
+    Ok(dump_files)
+}
+
+/// Parses a percentage given on the command line
+///
+/// # Arguments
+///
+/// * `value` - The value of the option
+///
+/// # Returns
+///
+/// * `Result<f64, String>` - The percentage, between 0 and 100
+fn parse_percentage(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(percentage) if (0.0..=100.0).contains(&percentage) => Ok(percentage),
+        _ => Err(format!("{} is not a percentage between 0 and 100", value)),
+    }
+}
+
+/// Resolves the XML file of the model `T`
+///
+/// # Arguments
+///
+/// * `path` - The path given with the `--*-path` option of the table, if any
+/// * `dump_files` - The files found in the dump directory
+///
+/// # Returns
+///
+/// * `Option<PathBuf>` - The given path, or else the file of the dump directory named like `T::FILE_NAME`
+fn resolve_path<T>(path: &Option<PathBuf>, dump_files: &HashMap<String, PathBuf>) -> Option<PathBuf>
+where
+    T: Loadable,
+{
+    path.clone()
+        .or_else(|| dump_files.get(&T::FILE_NAME.to_lowercase()).cloned())
+}
+
+/// Resolves the XML file of every table
+///
+/// # Arguments
+///
+/// * `inputs` - The dump directory and XML files given on the command line
+///
+/// # Returns
+///
+/// * `Result<[Option<String>; 8], EtlError>` - The path of the XML file of every table of `model::TABLE_NAMES`, if one was given
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - The dump directory cannot be read
+/// - Several tables read standard input
+/// - A path is not valid UTF-8
+fn input_files(inputs: &InputArguments) -> Result<[Option<String>; 8], EtlError> {
+    // Find the XML files of the dump directory, if one was given.
+    let dump_files = match &inputs.dump_dir {
+        Some(dump_dir) => find_dump_files(dump_dir)
+            .context(|| format!("Error reading dump directory {}", dump_dir.display()))?,
+        None => HashMap::new(),
+    };
+
+    let paths = [
+        resolve_path::<Badge>(&inputs.badges_path, &dump_files),
+        resolve_path::<Comment>(&inputs.comments_path, &dump_files),
+        resolve_path::<Post>(&inputs.posts_path, &dump_files),
+        resolve_path::<PostHistory>(&inputs.post_history_path, &dump_files),
+        resolve_path::<PostLink>(&inputs.post_links_path, &dump_files),
+        resolve_path::<Tag>(&inputs.tags_path, &dump_files),
+        resolve_path::<User>(&inputs.users_path, &dump_files),
+        resolve_path::<Vote>(&inputs.votes_path, &dump_files),
+    ];
+
+    // Standard input can only be read once.
+    let stdin_tables = paths
+        .iter()
+        .filter(|path| path.as_deref() == Some(Path::new(source::STDIN_PATH)))
+        .count();
+
+    if stdin_tables > 1 {
+        return Err(EtlError::Config(
+            "only one XML file can be read from standard input".to_string(),
+        ));
+    }
+
+    let mut files: [Option<String>; 8] = Default::default();
+
+    for ((file, path), table) in files.iter_mut().zip(paths).zip(model::TABLE_NAMES) {
+        *file = path
+            .map(|path| {
+                path.to_str().map(str::to_string).ok_or_else(|| {
+                    EtlError::Config(format!(
+                        "{} is not a valid {} file path",
+                        path.display(),
+                        table
+                    ))
+                })
+            })
+            .transpose()?;
+    }
+
+    Ok(files)
+}
+
+/// The signature shared by the `load_*` functions.
+type LoadFn =
+    fn(&mut PgConnection, &str, &LoadOptions, &Rejects, &Progress) -> Result<(), EtlError>;
+
+/// The signature of `verify::verify_table` for every model.
+type VerifyFn =
+    fn(&mut PgConnection, &str, NonZeroUsize, bool) -> Result<verify::Verification, EtlError>;
+
+/// The signature of `export::export_table` for every model.
+type ExportFn = fn(&mut PgConnection, &Path, CopyFormat) -> Result<u64, EtlError>;
+
+/// Loads the rows of an XML file into the table of the model `T`
+///
+/// Rows are committed once per table, or every `--commit-interval` rows,
+/// together with a checkpoint recording the `Id` of the last committed row.
+///
+/// # Arguments
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `file_path` - Path to the XML file
+/// * `options` - How rows are written to the database
+/// * `rejects` - Where the rows that cannot be loaded are written
+/// * `progress` - Where the rows and bytes read are counted
+///
+/// # Returns
+///
+/// * `Result<(), EtlError>` - Ok if successful, Error otherwise
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - XML file cannot be read or parsed
+/// - Database insert or copy operation fails
+fn load_table<T>(
+    connection: &mut PgConnection,
+    file_path: &str,
+    options: &LoadOptions,
+    rejects: &Rejects,
+    progress: &Progress,
+) -> Result<(), EtlError>
+where
+    T: Loadable + Send + 'static,
+{
+    // Find where a previous load stopped, when resuming
+    let checkpoint = match options.resume {
+        true => checkpoint::load(connection, T::TABLE_NAME)
+            .context(|| format!("Error reading checkpoint for {}", T::TABLE_NAME))?,
+        false => None,
+    };
+
+    if checkpoint
+        .as_ref()
+        .is_some_and(|checkpoint| checkpoint.completed)
+    {
+        info!("Skipping table, already loaded");
+        return Ok(());
+    }
+
+    // Stop maintaining the secondary indexes while the rows are written
+    if options.rebuild_indexes {
+        let dropped = indexes::drop_indexes(connection, T::TABLE_NAME)
+            .context(|| format!("Error dropping indexes of {}", T::TABLE_NAME))?;
+
+        info!(indexes = dropped.len(), "Dropped indexes");
+    }
+
+    let resume_after = checkpoint.and_then(|checkpoint| checkpoint.last_id);
+    let mut last_id = resume_after;
+
+    // Create iterator for model objects from XML file, counting the rows
+    // and bytes it reads
+    let table_progress = progress.start(T::TABLE_NAME);
+
+    let rows_iter: Box<dyn Iterator<Item = Result<T, RowError>>> =
+        match options.parse_threads.get() {
+            1 => XmlModelIterator::<T>::new(file_path, table_progress.bytes())
+                .map(|iter| Box::new(iter) as _),
+            threads => {
+                ParallelXmlModelIterator::<T>::new(file_path, threads, table_progress.bytes())
+                    .map(|iter| Box::new(iter) as _)
+            }
+        }
+        .context(|| format!("Error creating XML model iterator for {}", T::TABLE_NAME))?;
+
+    // Track the ids of the XML file to find the rows it does not have anymore,
+    // including the rows of a previous load skipped below, and the ids it
+    // has twice, which would be merged silently or break the merge
+    let mut seen = IdSet::default();
+    let duplicate = Cell::new(None);
+    let mut parse_errors = 0;
+
+    let rows_iter = rows_iter.inspect(|row| {
+        table_progress.row();
+
+        match row {
+            Ok(row) => {
+                if !seen.insert(row.id()) && duplicate.get().is_none() {
+                    duplicate.set(Some(row.id()));
+                }
+            }
+            Err(_) => parse_errors += 1,
+        }
+    });
+
+    // Fast-forward past the rows committed by the previous load, relying on
+    // dumps being sorted by Id, but stop at a read error
+    let mut rows_iter = rows_iter
+        .skip_while(|row| match (row, resume_after) {
+            (Ok(row), Some(last_id)) => row.id() <= last_id,
+            (Err(error), Some(_)) => error.row.is_some(),
+            (_, None) => false,
+        })
+        .peekable();
+
+    if let Some(last_id) = resume_after {
+        info!(last_id, "Resuming after the last committed row");
+    }
+
+    let commit_interval = options
+        .commit_interval
+        .map_or(usize::MAX, NonZeroUsize::get);
+
+    let table_rejects = TableRejects::new(
+        file_path,
+        T::TABLE_NAME,
+        rejects,
+        &table_progress,
+        options.max_errors,
+        options.max_error_rate,
+    );
+
+    loop {
+        let (completed, written) = connection.transaction(|connection| {
+            let mut reject_error = None;
+            let mut read_error = None;
+            let mut duplicate_id = None;
+
+            // Set the rows that cannot be parsed aside and go on with the next
+            // ones, until the error budget of the table is exceeded. A read
+            // error, e.g. of a truncated archive, leaves the rest of the file
+            // unread and fails the table.
+            let chunk = rows_iter
+                .by_ref()
+                .take(commit_interval)
+                .map_while(|row| {
+                    table_rejects.read();
+
+                    match row {
+                        Ok(row) if duplicate.get() == Some(row.id()) => {
+                            duplicate_id = Some(row.id());
+                            None
+                        }
+                        Ok(row) => {
+                            last_id = Some(row.id());
+                            Some(Some(row))
+                        }
+                        Err(error) if error.row.is_none() => {
+                            read_error = Some(error);
+                            None
+                        }
+                        Err(error) => match table_rejects.parse_error(&error) {
+                            Ok(()) => Some(None),
+                            Err(error) => {
+                                reject_error = Some(error);
+                                None
+                            }
+                        },
+                    }
+                })
+                .flatten();
+
+            // Write the rows to a temporary copy of the table when they have
+            // to be merged into the existing rows. Staging tables start
+            // empty, and have no primary key to merge on until loaded.
+            let shadow = match (options.on_conflict, options.staging) {
+                (OnConflict::Error, _) | (_, true) => None,
+                (OnConflict::Skip | OnConflict::Update, false) => {
+                    Some(upsert::shadow_table(connection, T::TABLE_NAME)?)
+                }
+            };
+
+            let mut written = write_rows(connection, chunk, options, &table_rejects)?;
+
+            if let Some(id) = duplicate_id {
+                return Err(EtlError::DuplicateId(id));
+            }
+
+            // Roll the chunk back, so the checkpoint is not marked completed
+            if let Some(error) = read_error {
+                return Err(EtlError::Xml(error.error))
+                    .context(|| format!("Error reading XML at byte {}", error.position));
+            }
+
+            if let Some(error) = reject_error {
+                return Err(error).context(|| format!("Error rejecting {} rows", T::TABLE_NAME));
+            }
+
+            // Only the rows inserted or updated by the merge reach the table
+            if let Some(shadow) = &shadow {
+                written = merge_shadow::<T>(
+                    connection,
+                    shadow,
+                    options.on_conflict == OnConflict::Update,
+                    &table_rejects,
+                )?;
+            }
+
+            // Record the progress in the same transaction as the rows
+            let completed = rows_iter.peek().is_none();
+            let checkpoint = checkpoint::Checkpoint {
+                table_name: T::TABLE_NAME.to_string(),
+                file_path: file_path.to_string(),
+                last_id,
+                completed,
+            };
+
+            checkpoint::save(connection, &checkpoint)
+                .context(|| format!("Error saving checkpoint for {}", T::TABLE_NAME))?;
+
+            Ok::<_, EtlError>((completed, written))
+        })?;
+
+        table_progress.written(written);
+        table_rejects
+            .commit()
+            .context(|| "Error writing rejects file".to_string())?;
+
+        if completed {
+            break;
+        }
+    }
+
+    drop(rows_iter);
+    table_progress.finish();
+
+    // Check the rate of rejected rows of the whole file, however few rows it has
+    table_rejects
+        .finish()
+        .context(|| format!("Error rejecting {} rows", T::TABLE_NAME))?;
+
+    if options.removed_rows != RemovedRows::Keep {
+        handle_removed_rows::<T>(connection, &seen, parse_errors, options)?;
+    }
+
+    Ok(())
+}
+
+/// Counts, deletes or marks the rows of the table of the model `T` whose
+/// `Id` is not in its XML file anymore
+///
+/// Nothing is changed if some rows of the XML file could not be parsed,
+/// since their ids are unknown.
+///
+/// With `--staging`, the removed rows are looked for in the live table, since
+/// the staging table only holds the new file. They are dropped by the swap,
+/// unless they are marked, which copies them to the staging table first.
+///
+/// # Arguments
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `seen` - The ids of the whole XML file
+/// * `parse_errors` - The number of rows of the XML file that could not be parsed
+/// * `options` - What happens to the removed rows
+///
+/// # Returns
+///
+/// * `Result<(), EtlError>` - Ok if successful, Error otherwise
+///
+/// # Errors
+///
+/// Returns an error if the removed rows cannot be counted, deleted or marked
+fn handle_removed_rows<T>(
+    connection: &mut PgConnection,
+    seen: &IdSet,
+    parse_errors: usize,
+    options: &LoadOptions,
+) -> Result<(), EtlError>
+where
+    T: Loadable,
+{
+    if parse_errors > 0 {
+        warn!(
+            parse_errors,
+            "Not looking for removed rows, some rows of the XML file could not be parsed"
+        );
+        return Ok(());
+    }
+
+    // The unqualified name resolves to the live table, and the staging table
+    // is named with its schema.
+    let staging_table = options
+        .staging
+        .then(|| format!("{}.{}", staging::STAGING_SCHEMA, T::TABLE_NAME));
+    if staging_table.is_some() {
+        staging::use_live(connection)?;
+    }
+
+    let handled = connection
+        .transaction(|connection| {
+            removed::copy_seen_ids(connection, seen)?;
+
+            let count = removed::count_removed(connection, T::TABLE_NAME)?;
+            info!(count, "Found removed rows");
+
+            match (options.removed_rows, &staging_table) {
+                (RemovedRows::Keep | RemovedRows::Count, _) => {}
+                // The swap drops the live table along with the removed rows.
+                (RemovedRows::Delete, Some(_)) => {}
+                (RemovedRows::Delete, None) => {
+                    let deleted = removed::delete_removed(connection, T::TABLE_NAME)?;
+                    info!(deleted, "Deleted removed rows");
+                }
+                (RemovedRows::Mark, Some(staging_table)) => {
+                    removed::copy_removed(connection, T::TABLE_NAME, staging_table)?;
+                    let marked = removed::mark_removed(connection, staging_table)?;
+                    info!(marked, "Marked removed rows");
+                }
+                (RemovedRows::Mark, None) => {
+                    let marked = removed::mark_removed(connection, T::TABLE_NAME)?;
+                    info!(marked, "Marked removed rows");
+                }
+            }
+
+            Ok::<_, EtlError>(())
+        })
+        .context(|| format!("Error handling removed {}", T::TABLE_NAME));
+
+    if staging_table.is_some() {
+        staging::use_staging(connection)?;
+    }
+
+    handled
+}
+
+/// Writes rows into the table of the model `T`
+///
+/// # Arguments
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `rows` - The parsed rows to write
+/// * `options` - How rows are written to the database
+/// * `table_rejects` - Where the rows refused by the database are counted
+///
+/// # Returns
+///
+/// * `Result<usize, EtlError>` - The number of rows written if successful, Error otherwise
+///
+/// # Errors
+///
+/// Returns an error if the database insert or copy operation fails, or too
+/// many rows are refused
+fn write_rows<T, I>(
+    connection: &mut PgConnection,
+    rows: I,
+    options: &LoadOptions,
+    table_rejects: &TableRejects,
+) -> Result<usize, EtlError>
+where
+    T: Loadable,
+    I: Iterator<Item = T>,
+{
+    let copy_format = match options.load_mode {
+        LoadMode::Insert => None,
+        LoadMode::CopyText => Some(CopyFormat::Text),
+        LoadMode::CopyBinary => Some(CopyFormat::Binary),
+    };
+
+    let batch_size = match copy_format {
+        Some(_) => COPY_BATCH_SIZE,
+        None => options.batch_size.get().min(T::max_batch_size()),
+    };
+
+    // Process the rows of the XML file in batches
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut written = 0;
+
+    for row in rows {
+        batch.push(row);
+
+        if batch.len() == batch_size {
+            written += write_batch(connection, &mut batch, copy_format, table_rejects)?;
+        }
+    }
+
+    // Write the remaining rows of the last, partial batch
+    written += write_batch(connection, &mut batch, copy_format, table_rejects)?;
+
+    Ok(written)
+}
+
+/// Loads an XML file into the staging copy of its table, then makes the copy
+/// durable and builds the constraints and indexes of the live table on it
+///
+/// # Arguments
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `table_name` - The table to load
+/// * `file_path` - Path to the XML file
+/// * `load` - The loader of the table
+/// * `options` - How rows are written to the database
+/// * `rejects` - Where the rows that cannot be loaded are written
+/// * `progress` - Where the rows and bytes read are counted
+///
+/// # Returns
+///
+/// * `Result<(), EtlError>` - Ok if successful, Error otherwise
+///
+/// # Errors
+///
+/// Returns an error if the staging table cannot be created, loaded or indexed
+fn load_staging_table(
+    connection: &mut PgConnection,
+    table_name: &str,
+    file_path: &str,
+    load: LoadFn,
+    options: &LoadOptions,
+    rejects: &Rejects,
+    progress: &Progress,
+) -> Result<(), EtlError> {
+    staging::create_table(connection, table_name)
+        .context(|| format!("Error creating staging table for {}", table_name))?;
+
+    // Point the models at the staging table for the duration of the load.
+    staging::use_staging(connection)?;
+    let loaded = load(connection, file_path, options, rejects, progress);
+    staging::use_live(connection)?;
+    loaded?;
+
+    let indexes = staging::set_logged(connection, table_name)
+        .context(|| format!("Error logging staging table for {}", table_name))?;
+
+    for (position, index) in indexes.iter().enumerate() {
+        info!(
+            index = %index.index_name,
+            position = position + 1,
+            indexes = indexes.len(),
+            "Building index"
+        );
+
+        let started = Instant::now();
+
+        staging::build_index(connection, index)
+            .context(|| format!("Error building index {}", index.index_name))?;
+
+        info!(
+            index = %index.index_name,
+            elapsed_seconds = started.elapsed().as_secs_f64(),
+            "Built index"
+        );
+    }
+
+    Ok(())
+}
+
+/// Recreates the indexes of a table dropped by `--rebuild-indexes`
+///
+/// Up to `--index-jobs` indexes are built at once, each on its own pooled
+/// connection. Concurrent builds on the same table wait for each other to
+/// finish, so with `--create-indexes-concurrently` they run one at a time.
+///
+/// # Arguments
+///
+/// * `pool` - The pool of PostgreSQL database connections
+/// * `table_name` - The table to recreate the indexes of
+/// * `options` - How indexes are built
+///
+/// # Returns
+///
+/// * `Result<(), EtlError>` - Ok if successful, Error otherwise
+///
+/// # Errors
+///
+/// Returns an error if an index cannot be built
+fn rebuild_indexes(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    table_name: &str,
+    options: &LoadOptions,
+) -> Result<(), EtlError> {
+    let dropped = indexes::dropped_indexes(&mut *pool.get()?, table_name)
+        .context(|| format!("Error reading dropped indexes of {}", table_name))?;
+
+    let next_index = AtomicUsize::new(0);
+    let index_jobs = match options.create_indexes_concurrently {
+        true => 1,
+        false => options.index_jobs.get(),
+    }
+    .min(dropped.len());
+
+    // The builders log in the span of the table, like the job
+    let span = Span::current();
+
+    thread::scope(|scope| {
+        let builders: Vec<_> = (0..index_jobs)
+            .map(|_| {
+                scope.spawn(|| -> Result<(), EtlError> {
+                    let _span = span.enter();
+                    let mut connection = pool.get()?;
+
+                    loop {
+                        let position = next_index.fetch_add(1, Ordering::Relaxed);
+                        let Some(index) = dropped.get(position) else {
+                            return Ok(());
+                        };
+
+                        info!(
+                            index = %index.index_name,
+                            position = position + 1,
+                            indexes = dropped.len(),
+                            "Building index"
+                        );
+
+                        let started = Instant::now();
+
+                        indexes::create_index(
+                            &mut connection,
+                            index,
+                            options.create_indexes_concurrently,
+                        )
+                        .context(|| format!("Error building index {}", index.index_name))?;
+
+                        info!(
+                            index = %index.index_name,
+                            elapsed_seconds = started.elapsed().as_secs_f64(),
+                            "Built index"
+                        );
+                    }
+                })
+            })
+            .collect();
+
+        builders
+            .into_iter()
+            .try_for_each(|builder| builder.join().expect("index job panicked"))
+    })
+}
+
+/// Writes a batch of rows into the table of the model `T` and empties it
+///
+/// The batch is written under a savepoint. If a row breaks a constraint,
+/// the batch is rolled back and its rows inserted one at a time, so only the
+/// rows at fault are rejected.
+///
+/// # Arguments
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `batch` - The rows to write
+/// * `copy_format` - The `COPY` format to write the rows in, or `None` to insert them
+/// * `table_rejects` - Where the rows refused by the database are counted
+///
+/// # Returns
+///
+/// * `Result<usize, EtlError>` - The number of rows written if successful, Error otherwise
+///
+/// # Errors
+///
+/// Returns an error if the database insert or copy operation fails for
+/// another reason than a constraint, or too many rows are refused
+fn write_batch<T>(
+    connection: &mut PgConnection,
+    batch: &mut Vec<T>,
+    copy_format: Option<CopyFormat>,
+    table_rejects: &TableRejects,
+) -> Result<usize, EtlError>
+where
+    T: Loadable,
+{
+    if batch.is_empty() {
+        return Ok(0);
+    }
+
+    // Write the rows into the database
+    let written = connection.transaction(|connection| match copy_format {
+        Some(copy_format) => copy::copy_rows(connection, batch, copy_format),
+        None => T::insert(batch, connection).map_err(EtlError::from),
+    });
+
+    let written = match written {
+        Ok(written) => written,
+        Err(EtlError::Database(error)) if constraint_violation(&error).is_some() => {
+            let mut written = 0;
+
+            for row in batch.iter() {
+                let inserted = connection
+                    .transaction(|connection| T::insert(slice::from_ref(row), connection));
+
+                match inserted {
+                    Ok(inserted) => written += inserted,
+                    Err(error) => match constraint_violation(&error) {
+                        Some((kind, info)) => table_rejects
+                            .database_error(row.id(), kind, info)
+                            .context(|| format!("Error rejecting {} rows", T::TABLE_NAME))?,
+                        None => {
+                            return Err(error).context(|| {
+                                format!("Error inserting {} into database", T::TABLE_NAME)
+                            });
+                        }
+                    },
+                }
+            }
+
+            written
+        }
+        Err(error) => {
+            return Err(error).context(|| format!("Error writing {} into database", T::TABLE_NAME));
+        }
+    };
+
+    batch.clear();
+
+    Ok(written)
+}
+
+/// Merges the rows written to a temporary copy into the table of the model
+/// `T`
+///
+/// The rows are merged under a savepoint. If a row breaks a constraint,
+/// like a foreign key added by `check-references`, the merge is rolled back
+/// and the rows merged one at a time, so only the rows at fault are
+/// rejected.
+///
+/// # Arguments
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `shadow` - The temporary copy the rows were written to
+/// * `update` - Whether to update existing rows instead of skipping them
+/// * `table_rejects` - Where the rows refused by the database are counted
+///
+/// # Returns
+///
+/// * `Result<usize, EtlError>` - The number of rows inserted or updated if successful, Error otherwise
+///
+/// # Errors
+///
+/// Returns an error if the merge fails for another reason than a
+/// constraint, or too many rows are refused
+fn merge_shadow<T>(
+    connection: &mut PgConnection,
+    shadow: &upsert::Shadow,
+    update: bool,
+    table_rejects: &TableRejects,
+) -> Result<usize, EtlError>
+where
+    T: Loadable,
+{
+    let merged = connection.transaction(|connection| upsert::merge(connection, shadow, update));
+
+    match merged {
+        Ok(merged) => Ok(merged),
+        Err(error) if constraint_violation(&error).is_some() => {
+            let ids = upsert::shadow_ids(connection, shadow)
+                .context(|| format!("Error reading {} rows to merge", T::TABLE_NAME))?;
+            let mut merged = 0;
+
+            for id in ids {
+                let merged_row = connection
+                    .transaction(|connection| upsert::merge_row(connection, shadow, update, id));
+
+                match merged_row {
+                    Ok(merged_row) => merged += merged_row,
+                    Err(error) => match constraint_violation(&error) {
+                        Some((kind, info)) => table_rejects
+                            .database_error(id, kind, info)
+                            .context(|| format!("Error rejecting {} rows", T::TABLE_NAME))?,
+                        None => {
+                            return Err(error).context(|| {
+                                format!("Error merging {} into database", T::TABLE_NAME)
+                            });
+                        }
+                    },
+                }
+            }
+
+            Ok(merged)
+        }
+        Err(error) => {
+            Err(error).context(|| format!("Error merging {} into database", T::TABLE_NAME))
+        }
+    }
+}
+
+/// Returns the details of a database error if it is a constraint violation,
+/// which only concerns the rows breaking the constraint
+///
+/// A row whose `Id` is already in its table is not such a violation: it
+/// fails the load with `--on-conflict error`, and is merged on its `Id`
+/// otherwise. An `Id` appearing twice in the XML file fails the load before
+/// it is written, whatever `--on-conflict` says.
+///
+/// # Arguments
+///
+/// * `error` - The error returned by the database
+///
+/// # Returns
+///
+/// * `Option<(DatabaseErrorKind, &dyn DatabaseErrorInformation)>` - The kind and details of the violation, or `None` for other errors
+fn constraint_violation(
+    error: &diesel::result::Error,
+) -> Option<(DatabaseErrorKind, &dyn DatabaseErrorInformation)> {
+    match error {
+        diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, info)
+            if info
+                .table_name()
+                .zip(info.constraint_name())
+                .is_some_and(|(table, constraint)| constraint == format!("{}_pkey", table)) =>
+        {
+            None
+        }
+        diesel::result::Error::DatabaseError(
+            kind @ (DatabaseErrorKind::UniqueViolation
+            | DatabaseErrorKind::ForeignKeyViolation
+            | DatabaseErrorKind::NotNullViolation
+            | DatabaseErrorKind::CheckViolation),
+            info,
+        ) => Some((*kind, info.as_ref())),
+        _ => None,
+    }
+}
+
+/// Loads badge data from XML file into the database
+///
+/// # Arguments
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `file_path` - Path to the badges XML file
+/// * `options` - How rows are written to the database
+/// * `rejects` - Where the rows that cannot be loaded are written
+/// * `progress` - Where the rows and bytes read are counted
+///
+/// # Returns
+///
+/// * `Result<(), EtlError>` - Ok if successful, Error otherwise
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - XML file cannot be read or parsed
+/// - Database insert or copy operation fails
+fn load_badges(
+    connection: &mut PgConnection,
+    file_path: &str,
+    options: &LoadOptions,
+    rejects: &Rejects,
+    progress: &Progress,
+) -> Result<(), EtlError> {
+    load_table::<Badge>(connection, file_path, options, rejects, progress)
+}
+
+/// Loads comment data from XML file into the database
+///
+/// # Arguments
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `file_path` - Path to the comments XML file
+/// * `options` - How rows are written to the database
+/// * `rejects` - Where the rows that cannot be loaded are written
+/// * `progress` - Where the rows and bytes read are counted
+///
+/// # Returns
+///
+/// * `Result<(), EtlError>` - Ok if successful, Error otherwise
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - XML file cannot be read or parsed
+/// - Database insert or copy operation fails
+fn load_comments(
+    connection: &mut PgConnection,
+    file_path: &str,
+    options: &LoadOptions,
+    rejects: &Rejects,
+    progress: &Progress,
+) -> Result<(), EtlError> {
+    load_table::<Comment>(connection, file_path, options, rejects, progress)
+}
+
+/// Loads post data from XML file into the database
+///
+/// # Arguments
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `file_path` - Path to the posts XML file
+/// * `options` - How rows are written to the database
+/// * `rejects` - Where the rows that cannot be loaded are written
+/// * `progress` - Where the rows and bytes read are counted
+///
+/// # Returns
+///
+/// * `Result<(), EtlError>` - Ok if successful, Error otherwise
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - XML file cannot be read or parsed
+/// - Database insert or copy operation fails
+fn load_posts(
+    connection: &mut PgConnection,
+    file_path: &str,
+    options: &LoadOptions,
+    rejects: &Rejects,
+    progress: &Progress,
+) -> Result<(), EtlError> {
+    load_table::<Post>(connection, file_path, options, rejects, progress)
+}
+
+/// Loads post history data from XML file into the database
+///
+/// # Arguments
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `file_path` - Path to the post history XML file
+/// * `options` - How rows are written to the database
+/// * `rejects` - Where the rows that cannot be loaded are written
+/// * `progress` - Where the rows and bytes read are counted
+///
+/// # Returns
+///
+/// * `Result<(), EtlError>` - Ok if successful, Error otherwise
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - XML file cannot be read or parsed
+/// - Database insert or copy operation fails
+fn load_post_history(
+    connection: &mut PgConnection,
+    file_path: &str,
+    options: &LoadOptions,
+    rejects: &Rejects,
+    progress: &Progress,
+) -> Result<(), EtlError> {
+    load_table::<PostHistory>(connection, file_path, options, rejects, progress)
+}
+
+/// Loads post links data from XML file into the database
+///
+/// # Arguments
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `file_path` - Path to the post links XML file
+/// * `options` - How rows are written to the database
+/// * `rejects` - Where the rows that cannot be loaded are written
+/// * `progress` - Where the rows and bytes read are counted
+///
+/// # Returns
+///
+/// * `Result<(), EtlError>` - Ok if successful, Error otherwise
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - XML file cannot be read or parsed
+/// - Database insert or copy operation fails
+fn load_post_links(
+    connection: &mut PgConnection,
+    file_path: &str,
+    options: &LoadOptions,
+    rejects: &Rejects,
+    progress: &Progress,
+) -> Result<(), EtlError> {
+    load_table::<PostLink>(connection, file_path, options, rejects, progress)
+}
+
+/// Loads tag data from XML file into the database
+///
+/// # Arguments
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `file_path` - Path to the tags XML file
+/// * `options` - How rows are written to the database
+/// * `rejects` - Where the rows that cannot be loaded are written
+/// * `progress` - Where the rows and bytes read are counted
+///
+/// # Returns
+///
+/// * `Result<(), EtlError>` - Ok if successful, Error otherwise
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - XML file cannot be read or parsed
+/// - Database insert or copy operation fails
+fn load_tags(
+    connection: &mut PgConnection,
+    file_path: &str,
+    options: &LoadOptions,
+    rejects: &Rejects,
+    progress: &Progress,
+) -> Result<(), EtlError> {
+    load_table::<Tag>(connection, file_path, options, rejects, progress)
+}
+
+/// Loads user data from XML file into the database
+///
+/// # Arguments
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `file_path` - Path to the users XML file
+/// * `options` - How rows are written to the database
+/// * `rejects` - Where the rows that cannot be loaded are written
+/// * `progress` - Where the rows and bytes read are counted
+///
+/// # Returns
+///
+/// * `Result<(), EtlError>` - Ok if successful, Error otherwise
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - XML file cannot be read or parsed
+/// - Database insert or copy operation fails
+fn load_users(
+    connection: &mut PgConnection,
+    file_path: &str,
+    options: &LoadOptions,
+    rejects: &Rejects,
+    progress: &Progress,
+) -> Result<(), EtlError> {
+    load_table::<User>(connection, file_path, options, rejects, progress)
 }
 
-/// Loads badge data from XML file into the database
+/// Loads vote data from XML file into the database
 ///
 /// # Arguments
 ///
 /// * `connection` - A mutable reference to the PostgreSQL database connection
-/// * `file_path` - Path to the badges XML file
+/// * `file_path` - Path to the votes XML file
+/// * `options` - How rows are written to the database
+/// * `rejects` - Where the rows that cannot be loaded are written
+/// * `progress` - Where the rows and bytes read are counted
 ///
 /// # Returns
 ///
-/// * `Result<(), Box<dyn std::error::Error>>` - Ok if successful, Error otherwise
+/// * `Result<(), EtlError>` - Ok if successful, Error otherwise
 ///
 /// # Errors
 ///
 /// Returns an error if:
 /// - XML file cannot be read or parsed
-/// - Database insert operation fails
-fn load_badges(
+/// - Database insert or copy operation fails
+fn load_votes(
     connection: &mut PgConnection,
     file_path: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Create iterator for Badge objects from XML file
-    let badges_iter = XmlModelIterator::<Badge>::new(file_path)
-        .map_err(|error| format!("Error creating XML model iterator for badges: {}", error))?;
-
-    // Process each badge in the XML file
-    for badge in badges_iter {
-        match badge {
-            Ok(badge) => {
-                // Insert the badge into the database
-                diesel::insert_into(schema::badges::table)
-                    .values(&badge)
-                    .execute(connection)
-                    .map_err(|error| format!("Error inserting badge into database: {}", error))?;
-            }
-            Err(error) => eprintln!("Error processing badge: {}", error), // Log error but continue processing
-        }
-    }
+    options: &LoadOptions,
+    rejects: &Rejects,
+    progress: &Progress,
+) -> Result<(), EtlError> {
+    load_table::<Vote>(connection, file_path, options, rejects, progress)
+}
 
-    Ok(())
+/// Connects to the PostgreSQL database
+///
+/// # Arguments
+///
+/// * `database_url` - The URL of the database
+///
+/// # Returns
+///
+/// * `Result<PgConnection, EtlError>` - The connection if successful, Error otherwise
+///
+/// # Errors
+///
+/// Returns an error if the database cannot be reached
+fn connect(database_url: &str) -> Result<PgConnection, EtlError> {
+    PgConnection::establish(database_url).map_err(|error| EtlError::connection(database_url, error))
 }
 
-/// Loads comment data from XML file into the database
+/// Runs the pending migrations and reports the applied versions
 ///
 /// # Arguments
 ///
-/// * `connection` - A mutable reference to the PostgreSQL database connection
-/// * `file_path` - Path to the comments XML file
+/// * `args` - The arguments of the `migrate` subcommand
 ///
 /// # Returns
 ///
-/// * `Result<(), Box<dyn std::error::Error>>` - Ok if successful, Error otherwise
+/// * `Result<(), EtlError>` - Ok if successful, Error otherwise
 ///
 /// # Errors
 ///
-/// Returns an error if:
-/// - XML file cannot be read or parsed
-/// - Database insert operation fails
-fn load_comments(
-    connection: &mut PgConnection,
-    file_path: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Create iterator for Comment objects from XML file
-    let comments_iter = XmlModelIterator::<Comment>::new(file_path)
-        .map_err(|error| format!("Error creating XML model iterator for comments: {}", error))?;
-
-    // Process each comment in the XML file
-    for comment in comments_iter {
-        match comment {
-            Ok(comment) => {
-                // Insert the comment into the database
-                diesel::insert_into(schema::comments::table)
-                    .values(&comment)
-                    .execute(connection)
-                    .map_err(|error| format!("Error inserting comment into database: {}", error))?;
-            }
-            Err(error) => eprintln!("Error processing comment: {}", error), // Log error but continue processing
+/// Returns an error if the database cannot be reached or a migration fails
+fn migrate(args: &MigrateArguments) -> Result<(), EtlError> {
+    // Connect to the PostgreSQL database.
+    let mut connection = connect(&args.database_url)?;
+
+    if !args.list {
+        let applied = migrations::run_pending(&mut connection)
+            .context(|| "Failed to run migrations".to_string())?;
+
+        for version in &applied {
+            info!(version = %version, "Applied migration");
         }
     }
 
+    let status =
+        migrations::status(&mut connection).context(|| "Failed to read migrations".to_string())?;
+
+    for version in &status.applied {
+        println!("Applied: {}", version);
+    }
+
+    for version in &status.pending {
+        println!("Pending: {}", version);
+    }
+
+    if status.pending.is_empty() {
+        println!("The database schema is up to date.");
+    }
+
     Ok(())
 }
 
-/// Loads post data from XML file into the database
+/// Refreshes every materialized view, reporting the time each one took
 ///
 /// # Arguments
 ///
 /// * `connection` - A mutable reference to the PostgreSQL database connection
-/// * `file_path` - Path to the posts XML file
 ///
 /// # Returns
 ///
-/// * `Result<(), Box<dyn std::error::Error>>` - Ok if successful, Error otherwise
+/// * `Result<(), EtlError>` - Ok if successful, Error otherwise
 ///
 /// # Errors
 ///
-/// Returns an error if:
-/// - XML file cannot be read or parsed
-/// - Database insert operation fails
-fn load_posts(
-    connection: &mut PgConnection,
-    file_path: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Create iterator for Post objects from XML file
-    let posts_iter = XmlModelIterator::<Post>::new(file_path)
-        .map_err(|error| format!("Error creating XML model iterator for posts: {}", error))?;
-
-    // Process each post in the XML file
-    for post in posts_iter {
-        match post {
-            Ok(post) => {
-                // Insert the post into the database
-                diesel::insert_into(schema::posts::table)
-                    .values(&post)
-                    .execute(connection)
-                    .map_err(|error| format!("Error inserting post into database: {}", error))?;
-            }
-            Err(error) => eprintln!("Error processing post: {}", error), // Log error but continue processing
-        }
+/// Returns an error if the views cannot be listed or a refresh fails
+fn refresh_materialized_views(connection: &mut PgConnection) -> Result<(), EtlError> {
+    for view in views::materialized_views(connection)? {
+        let mode = match view.concurrently {
+            true => "concurrently",
+            false => "exclusively",
+        };
+
+        info!(view = %view.view_name, mode, "Refreshing materialized view");
+
+        let started = Instant::now();
+
+        views::refresh(connection, &view)
+            .context(|| format!("Error refreshing {}", view.view_name))?;
+
+        info!(
+            view = %view.view_name,
+            elapsed_seconds = started.elapsed().as_secs_f64(),
+            "Refreshed materialized view"
+        );
     }
 
     Ok(())
 }
 
-/// Loads post history data from XML file into the database
+/// Refreshes the materialized views
 ///
 /// # Arguments
 ///
-/// * `connection` - A mutable reference to the PostgreSQL database connection
-/// * `file_path` - Path to the post history XML file
+/// * `args` - The arguments of the `refresh-views` subcommand
 ///
 /// # Returns
 ///
-/// * `Result<(), Box<dyn std::error::Error>>` - Ok if successful, Error otherwise
+/// * `Result<(), EtlError>` - Ok if successful, Error otherwise
 ///
 /// # Errors
 ///
-/// Returns an error if:
-/// - XML file cannot be read or parsed
-/// - Database insert operation fails
-fn load_post_history(
-    connection: &mut PgConnection,
-    file_path: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Create iterator for PostHistory objects from XML file
-    let post_history_iter = XmlModelIterator::<PostHistory>::new(file_path).map_err(|error| {
-        format!(
-            "Error creating XML model iterator for post history: {}",
-            error
-        )
-    })?;
+/// Returns an error if the database cannot be reached or a refresh fails
+fn refresh_views(args: &RefreshViewsArguments) -> Result<(), EtlError> {
+    // Connect to the PostgreSQL database.
+    let mut connection = connect(&args.database_url)?;
 
-    // Process each post history record in the XML file
-    for post_history in post_history_iter {
-        match post_history {
-            Ok(post_history) => {
-                // Insert the post history record into the database
-                diesel::insert_into(schema::post_historys::table)
-                    .values(&post_history)
-                    .execute(connection)
-                    .map_err(|error| {
-                        format!("Error inserting post history into database: {}", error)
-                    })?;
-            }
-            Err(error) => eprintln!("Error processing post history: {}", error), // Log error but continue processing
-        }
+    refresh_materialized_views(&mut connection)
+        .context(|| "Failed to refresh materialized views".to_string())
+}
+
+/// Prints the statistics of every table
+///
+/// # Arguments
+///
+/// * `args` - The arguments of the `stats` subcommand
+///
+/// # Returns
+///
+/// * `Result<(), EtlError>` - Ok if successful, Error otherwise
+///
+/// # Errors
+///
+/// Returns an error if the database cannot be reached or a table cannot be read
+fn stats(args: &StatsArguments) -> Result<(), EtlError> {
+    // Connect to the PostgreSQL database.
+    let mut connection = connect(&args.database_url)?;
+
+    println!(
+        "{:<16} {:>12} {:>12} {:>12} {:>10}  Load",
+        "Table", "Rows", "Min Id", "Max Id", "Size"
+    );
+
+    for table in model::TABLE_NAMES {
+        let (table_stats, checkpoint) = stats::table_stats(&mut connection, table)
+            .context(|| format!("Failed to read statistics of {}", table))?;
+
+        let load = match checkpoint {
+            Some(checkpoint) if checkpoint.completed => "completed".to_string(),
+            Some(checkpoint) => match checkpoint.last_id {
+                Some(last_id) => format!("interrupted after Id {}", last_id),
+                None => "interrupted".to_string(),
+            },
+            None => "not loaded".to_string(),
+        };
+
+        let id = |id: Option<i32>| id.map_or("-".to_string(), |id| id.to_string());
+
+        println!(
+            "{:<16} {:>12} {:>12} {:>12} {:>10}  {}",
+            table,
+            table_stats.rows,
+            id(table_stats.min_id),
+            id(table_stats.max_id),
+            table_stats.total_size,
+            load
+        );
     }
 
     Ok(())
 }
 
-/// Loads post links data from XML file into the database
+/// Counts the orphan rows of every reference between the tables, applies
+/// the orphan policy and adds or drops the foreign keys
 ///
 /// # Arguments
 ///
-/// * `connection` - A mutable reference to the PostgreSQL database connection
-/// * `file_path` - Path to the post links XML file
+/// * `args` - The arguments of the `check-references` subcommand
 ///
 /// # Returns
 ///
-/// * `Result<(), Box<dyn std::error::Error>>` - Ok if successful, Error otherwise
+/// * `Result<(), EtlError>` - Ok if successful, Error otherwise
 ///
 /// # Errors
 ///
-/// Returns an error if:
-/// - XML file cannot be read or parsed
-/// - Database insert operation fails
-fn load_post_links(
-    connection: &mut PgConnection,
-    file_path: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Create iterator for PostLink objects from XML file
-    let post_links_iter = XmlModelIterator::<PostLink>::new(file_path).map_err(|error| {
-        format!(
-            "Error creating XML model iterator for post links: {}",
-            error
-        )
-    })?;
+/// Returns an error if the database cannot be reached or a statement fails,
+/// in which case nothing is changed
+fn check_references(args: &CheckReferencesArguments) -> Result<(), EtlError> {
+    // Connect to the PostgreSQL database.
+    let mut connection = connect(&args.database_url)?;
+
+    let checks = connection.transaction(|connection| {
+        let mut checks = Vec::new();
+
+        for reference in &references::REFERENCES {
+            let orphans = references::count_orphans(connection, reference)
+                .context(|| format!("Error counting the orphans of {}", reference))?;
+
+            let action = match (orphans, args.orphans, reference.nullable) {
+                (0, _, _) => "-",
+                (_, OrphanPolicy::Keep, _) => "kept",
+                (_, OrphanPolicy::Nullify | OrphanPolicy::Delete, true) => {
+                    let updated = references::nullify_orphans(connection, reference)
+                        .context(|| format!("Error clearing the orphans of {}", reference))?;
+
+                    info!(reference = %reference, rows = updated, "Set orphan references to NULL");
+                    "set to NULL"
+                }
+                (_, OrphanPolicy::Nullify, false) => "kept, not nullable",
+                (_, OrphanPolicy::Delete, false) => {
+                    let deleted = references::delete_orphans(connection, reference)
+                        .context(|| format!("Error deleting the orphans of {}", reference))?;
+
+                    info!(reference = %reference, rows = deleted, "Deleted orphan rows");
+                    "deleted"
+                }
+            };
+
+            if args.drop_foreign_keys {
+                references::drop_foreign_key(connection, reference)
+                    .context(|| format!("Error dropping the foreign key of {}", reference))?;
+            }
+
+            let mut foreign_key = references::foreign_key(connection, reference)
+                .context(|| format!("Error reading the foreign key of {}", reference))?;
+
+            if args.add_foreign_keys && foreign_key.is_none() {
+                references::add_foreign_key(connection, reference)
+                    .context(|| format!("Error adding the foreign key of {}", reference))?;
 
-    // Process each post link in the XML file
-    for post_link in post_links_iter {
-        match post_link {
-            Ok(post_link) => {
-                // Insert the post link into the database
-                diesel::insert_into(schema::post_links::table)
-                    .values(&post_link)
-                    .execute(connection)
-                    .map_err(|error| {
-                        format!("Error inserting post link into database: {}", error)
-                    })?;
+                info!(
+                    reference = %reference,
+                    constraint = reference.constraint_name(),
+                    "Added foreign key"
+                );
+                foreign_key = Some(false);
             }
-            Err(error) => eprintln!("Error processing post link: {}", error), // Log error but continue processing
+
+            checks.push((reference, orphans, action, foreign_key));
         }
+
+        Ok::<_, EtlError>(checks)
+    })?;
+
+    println!(
+        "{:<40} {:>12}  {:<20} Foreign key",
+        "Reference", "Orphans", "Action"
+    );
+
+    for (reference, orphans, action, foreign_key) in checks {
+        let foreign_key = match foreign_key {
+            Some(true) => "valid",
+            Some(false) => "not valid",
+            None => "none",
+        };
+
+        println!(
+            "{:<40} {:>12}  {:<20} {}",
+            reference.to_string(),
+            orphans,
+            action,
+            foreign_key
+        );
     }
 
     Ok(())
 }
 
-/// Loads tag data from XML file into the database
+/// Compares the given XML files with their tables, and prints how they
+/// differ
 ///
 /// # Arguments
 ///
-/// * `connection` - A mutable reference to the PostgreSQL database connection
-/// * `file_path` - Path to the tags XML file
+/// * `args` - The arguments of the `verify` subcommand
 ///
 /// # Returns
 ///
-/// * `Result<(), Box<dyn std::error::Error>>` - Ok if successful, Error otherwise
+/// * `Result<(), EtlError>` - Ok if every table matches its XML file, Error otherwise
 ///
 /// # Errors
 ///
-/// Returns an error if:
-/// - XML file cannot be read or parsed
-/// - Database insert operation fails
-fn load_tags(
-    connection: &mut PgConnection,
-    file_path: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Create iterator for Tag objects from XML file
-    let tags_iter = XmlModelIterator::<Tag>::new(file_path)
-        .map_err(|error| format!("Error creating XML model iterator for tags: {}", error))?;
-
-    // Process each tag in the XML file
-    for tag in tags_iter {
-        match tag {
-            Ok(tag) => {
-                // Insert the tag into the database
-                diesel::insert_into(schema::tags::table)
-                    .values(&tag)
-                    .execute(connection)
-                    .map_err(|error| format!("Error inserting tag into database: {}", error))?;
-            }
-            Err(error) => eprintln!("Error processing tag: {}", error), // Log error but continue processing
+/// Returns an error if the arguments are invalid, the database cannot be
+/// reached, an XML file or a table cannot be read, or any table does not
+/// match its XML file
+fn verify(args: &VerifyArguments) -> Result<(), EtlError> {
+    let files = input_files(&args.inputs)?;
+    let verifiers: [VerifyFn; 8] = [
+        verify::verify_table::<Badge>,
+        verify::verify_table::<Comment>,
+        verify::verify_table::<Post>,
+        verify::verify_table::<PostHistory>,
+        verify::verify_table::<PostLink>,
+        verify::verify_table::<Tag>,
+        verify::verify_table::<User>,
+        verify::verify_table::<Vote>,
+    ];
+
+    let mut verifications = Vec::new();
+
+    for ((table, path), verify_table) in model::TABLE_NAMES.iter().zip(&files).zip(verifiers) {
+        let Some(path) = path else {
+            info!(table, "Skipping table, no XML file given");
+            continue;
+        };
+
+        let span = info_span!("verify", table, file = path);
+        let _span = span.enter();
+
+        // Diesel does not read the end of a `COPY ... TO`, which leaves the
+        // connection unusable, so every table gets its own.
+        let mut connection = connect(&args.database_url)?;
+
+        let verification = verify_table(&mut connection, path, args.parse_threads, args.checksums)
+            .context(|| format!("Failed to verify {}", table))?;
+
+        match verification.differences.is_empty() {
+            true => info!(rows = verification.file.rows, "Table matches its XML file"),
+            false => warn!(
+                differences = %verification.differences.join("; "),
+                "Table does not match its XML file"
+            ),
         }
+
+        verifications.push((*table, verification));
+    }
+
+    println!(
+        "{:<16} {:>12} {:>12}  Result",
+        "Table", "XML rows", "Table rows"
+    );
+
+    let mut mismatches = Vec::new();
+
+    for (table, verification) in &verifications {
+        let result = match verification.differences.is_empty() {
+            true => "match".to_string(),
+            false => {
+                mismatches.push(table.to_string());
+                verification.differences.join("; ")
+            }
+        };
+
+        println!(
+            "{:<16} {:>12} {:>12}  {}",
+            table, verification.file.rows, verification.table.rows, result
+        );
+    }
+
+    if !mismatches.is_empty() {
+        return Err(EtlError::Mismatch(mismatches));
     }
 
     Ok(())
 }
 
-/// Loads user data from XML file into the database
+/// Copies the tables to files in the output directory, and prints their
+/// size
 ///
 /// # Arguments
 ///
-/// * `connection` - A mutable reference to the PostgreSQL database connection
-/// * `file_path` - Path to the users XML file
+/// * `args` - The arguments of the `export` subcommand
 ///
 /// # Returns
 ///
-/// * `Result<(), Box<dyn std::error::Error>>` - Ok if successful, Error otherwise
+/// * `Result<(), EtlError>` - Ok if successful, Error otherwise
 ///
 /// # Errors
 ///
-/// Returns an error if:
-/// - XML file cannot be read or parsed
-/// - Database insert operation fails
-fn load_users(
-    connection: &mut PgConnection,
-    file_path: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Create iterator for User objects from XML file
-    let users_iter = XmlModelIterator::<User>::new(file_path)
-        .map_err(|error| format!("Error creating XML model iterator for users: {}", error))?;
-
-    // Process each user in the XML file
-    for user in users_iter {
-        match user {
-            Ok(user) => {
-                // Insert the user into the database
-                diesel::insert_into(schema::users::table)
-                    .values(&user)
-                    .execute(connection)
-                    .map_err(|error| format!("Error inserting user into database: {}", error))?;
-            }
-            Err(error) => eprintln!("Error processing user: {}", error), // Log error but continue processing
+/// Returns an error if the output directory cannot be created, the
+/// database cannot be reached, or a table cannot be read or written
+fn export(args: &ExportArguments) -> Result<(), EtlError> {
+    let format = match args.format {
+        ExportFormat::Csv => CopyFormat::Csv,
+        ExportFormat::Text => CopyFormat::Text,
+    };
+    let exporters: [ExportFn; 8] = [
+        export::export_table::<Badge>,
+        export::export_table::<Comment>,
+        export::export_table::<Post>,
+        export::export_table::<PostHistory>,
+        export::export_table::<PostLink>,
+        export::export_table::<Tag>,
+        export::export_table::<User>,
+        export::export_table::<Vote>,
+    ];
+
+    std::fs::create_dir_all(&args.output_dir).context(|| {
+        format!(
+            "Error creating output directory {}",
+            args.output_dir.display()
+        )
+    })?;
+
+    let mut exports = Vec::new();
+
+    for (table, export_table) in model::TABLE_NAMES.iter().zip(exporters) {
+        if !args.tables.is_empty() && !args.tables.iter().any(|name| name == table) {
+            continue;
         }
+
+        let path = export::export_path(&args.output_dir, table, format);
+        let span = info_span!("export", table, file = %path.display());
+        let _span = span.enter();
+
+        // Diesel does not read the end of a `COPY ... TO`, which leaves the
+        // connection unusable, so every table gets its own.
+        let mut connection = connect(&args.database_url)?;
+
+        let bytes = export_table(&mut connection, &path, format)
+            .context(|| format!("Failed to export {}", table))?;
+
+        info!(bytes, "Exported table");
+        exports.push((*table, bytes, path));
+    }
+
+    println!("{:<16} {:>14}  File", "Table", "Bytes");
+
+    for (table, bytes, path) in exports {
+        println!("{:<16} {:>14}  {}", table, bytes, path.display());
     }
 
     Ok(())
 }
 
-/// Loads vote data from XML file into the database
+/// Loads the given XML files into the database
 ///
 /// # Arguments
 ///
-/// * `connection` - A mutable reference to the PostgreSQL database connection
-/// * `file_path` - Path to the votes XML file
+/// * `args` - The arguments of the `load` subcommand
+/// * `log_format` - How messages are logged
 ///
 /// # Returns
 ///
-/// * `Result<(), Box<dyn std::error::Error>>` - Ok if successful, Error otherwise
+/// * `Result<(), EtlError>` - Ok if successful, Error otherwise
 ///
 /// # Errors
 ///
-/// Returns an error if:
-/// - XML file cannot be read or parsed
-/// - Database insert operation fails
-fn load_votes(
-    connection: &mut PgConnection,
-    file_path: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Create iterator for Vote objects from XML file
-    let votes_iter = XmlModelIterator::<Vote>::new(file_path)
-        .map_err(|error| format!("Error creating XML model iterator for votes: {}", error))?;
-
-    // Process each vote in the XML file
-    for vote in votes_iter {
-        match vote {
-            Ok(vote) => {
-                // Insert the vote into the database
-                diesel::insert_into(schema::votes::table)
-                    .values(&vote)
-                    .execute(connection)
-                    .map_err(|error| format!("Error inserting vote into database: {}", error))?;
+/// Returns an error if the arguments are invalid, the database cannot be
+/// reached or any table fails to load
+fn load(args: &LoadArguments, log_format: LogFormat) -> Result<(), EtlError> {
+    // Pair every table with its XML file and loader, in load order.
+    let files = input_files(&args.inputs)?;
+    let loaders: [LoadFn; 8] = [
+        load_badges,
+        load_comments,
+        load_posts,
+        load_post_history,
+        load_post_links,
+        load_tags,
+        load_users,
+        load_votes,
+    ];
+
+    // Only the tables whose XML file was given are loaded.
+    let mut loads = Vec::new();
+
+    for ((table, path), load) in model::TABLE_NAMES.iter().zip(&files).zip(loaders) {
+        let Some(path) = path else {
+            info!(table, "Skipping table, no XML file given");
+            continue;
+        };
+
+        loads.push((*table, path.as_str(), load));
+    }
+
+    // Connect to the PostgreSQL database, with one connection per job.
+    let database_url = args.database_url.to_string();
+    let jobs = args
+        .jobs
+        .or_else(|| thread::available_parallelism().ok())
+        .map_or(1, NonZeroUsize::get)
+        .clamp(1, loads.len().max(1));
+
+    let manager = ConnectionManager::<PgConnection>::new(&database_url);
+
+    // Fail fast on an unreachable database instead of waiting for the pool to
+    // time out.
+    let mut connection = manager
+        .connect()
+        .map_err(|error| EtlError::connection(&database_url, error))?;
+
+    // Create or upgrade the schema before any table is loaded.
+    if args.auto_migrate {
+        let applied = migrations::run_pending(&mut connection)
+            .context(|| "Failed to run migrations".to_string())?;
+
+        for version in &applied {
+            info!(version = %version, "Applied migration");
+        }
+    }
+
+    // Create the staging schema once, before the jobs create their tables.
+    if args.load_options.staging {
+        staging::create_schema(&mut connection)
+            .context(|| "Failed to create the staging schema".to_string())?;
+    }
+
+    drop(connection);
+
+    // Every job can build several indexes at once after loading its table.
+    let index_jobs =
+        match args.load_options.rebuild_indexes && !args.load_options.create_indexes_concurrently {
+            true => args.load_options.index_jobs.get(),
+            false => 1,
+        };
+
+    let pool = Pool::builder()
+        .max_size((jobs * index_jobs) as u32)
+        .min_idle(Some(jobs as u32))
+        .build(manager)
+        .map_err(|error| EtlError::connection(&database_url, error))?;
+
+    let rejects = Rejects::create(args.rejects_path.as_deref(), args.load_options.resume)
+        .context(|| "Error creating rejects file".to_string())?;
+
+    // A bar would break the lines of JSON logs.
+    let progress_style = match args.progress {
+        ProgressMode::Auto if io::stderr().is_terminal() && log_format == LogFormat::Text => {
+            Some(progress::Style::Bar)
+        }
+        ProgressMode::Auto | ProgressMode::Log => Some(progress::Style::Log),
+        ProgressMode::Bar => Some(progress::Style::Bar),
+        ProgressMode::Off => None,
+    };
+    let progress = Progress::new(
+        progress_style,
+        Duration::from_secs(args.progress_interval.get()),
+        args.report_path.is_some(),
+    );
+    let report = Report::new();
+
+    // Every job takes the next table in load order until none are left, and
+    // stops early once any table has failed.
+    let next_load = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+
+    let failures: Vec<EtlError> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut failures = Vec::new();
+
+                    while !failed.load(Ordering::Relaxed) {
+                        let position = next_load.fetch_add(1, Ordering::Relaxed);
+                        let Some((table, path, load)) = loads.get(position) else {
+                            break;
+                        };
+
+                        // Load the XML file into the database, committing as it goes,
+                        // then recreate the indexes dropped for the load.
+                        let span = info_span!("load", table, file = path);
+                        let _span = span.enter();
+                        let started = Instant::now();
+
+                        let result = pool
+                            .get()
+                            .map_err(Into::into)
+                            .and_then(|mut connection| match args.load_options.staging {
+                                true => load_staging_table(
+                                    &mut connection,
+                                    table,
+                                    path,
+                                    *load,
+                                    &args.load_options,
+                                    &rejects,
+                                    &progress,
+                                ),
+                                false => load(
+                                    &mut connection,
+                                    path,
+                                    &args.load_options,
+                                    &rejects,
+                                    &progress,
+                                ),
+                            })
+                            .and_then(|_| match args.load_options.rebuild_indexes {
+                                true => rebuild_indexes(&pool, table, &args.load_options),
+                                false => Ok(()),
+                            });
+
+                        report.add(
+                            table,
+                            path,
+                            started.elapsed(),
+                            result.as_ref().err(),
+                            &progress,
+                        );
+
+                        if let Err(error) = result {
+                            failed.store(true, Ordering::Relaxed);
+                            failures.push((
+                                position,
+                                EtlError::Table {
+                                    table: table.to_string(),
+                                    source: Box::new(error),
+                                },
+                            ));
+                        }
+                    }
+
+                    failures
+                })
+            })
+            .collect();
+
+        let mut failures: Vec<_> = workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("load job panicked"))
+            .collect();
+
+        // Report the failures in load order, whichever job hit them, so the
+        // exit code does not depend on the scheduling of the jobs.
+        failures.sort_by_key(|(position, _)| *position);
+        failures.into_iter().map(|(_, error)| error).collect()
+    });
+
+    // Keep the rejected rows committed by the failed tables too.
+    rejects
+        .flush()
+        .context(|| "Error writing rejects file".to_string())?;
+
+    // Summarize the tables, including the failed ones.
+    if let Some(report_path) = &args.report_path {
+        report
+            .write(report_path)
+            .context(|| format!("Error writing report {}", report_path.display()))?;
+    }
+
+    if !failures.is_empty() {
+        return Err(EtlError::Tables(failures));
+    }
+
+    // Replace the live tables with the loaded staging tables all at once.
+    if args.load_options.staging && !loads.is_empty() {
+        let table_names: Vec<&str> = loads.iter().map(|(table, _, _)| *table).collect();
+
+        let swap = pool
+            .get()
+            .map_err(EtlError::from)
+            .and_then(|mut connection| Ok(staging::swap_tables(&mut connection, &table_names)?))
+            .context(|| "Failed to swap staging tables into place".to_string())?;
+
+        info!(tables = %table_names.join(", "), "Swapped staging tables into place");
+
+        for view in &swap.views {
+            info!(view = %view, "Recreated view");
+        }
+
+        // The new rows were not checked, so validated keys are not anymore
+        for foreign_key in &swap.foreign_keys {
+            match foreign_key.validated {
+                true => warn!(
+                    table = %foreign_key.table_name,
+                    constraint = %foreign_key.constraint_name,
+                    "Recreated validated foreign key as NOT VALID"
+                ),
+                false => info!(
+                    table = %foreign_key.table_name,
+                    constraint = %foreign_key.constraint_name,
+                    "Recreated foreign key as NOT VALID"
+                ),
             }
-            Err(error) => eprintln!("Error processing vote: {}", error), // Log error but continue processing
         }
     }
 
+    // Bring the materialized views up to date with the loaded rows. Views
+    // depending on swapped tables were already recreated from the new rows.
+    if !args.skip_refresh_views && !args.load_options.staging && !loads.is_empty() {
+        pool.get()
+            .map_err(Into::into)
+            .and_then(|mut connection| refresh_materialized_views(&mut connection))
+            .context(|| "Failed to refresh materialized views".to_string())?;
+    }
+
+    info!("Data loaded successfully into the database");
+
     Ok(())
 }
 
-fn main() {
+fn main() -> ExitCode {
     // Parse the command-line arguments.
     let args: Arguments = Arguments::parse();
 
-    // Connect to the PostgreSQL database.
-    let database_url = args.database_url.to_string();
+    let level = match args.log_level {
+        LogLevel::Error => Level::ERROR,
+        LogLevel::Warn => Level::WARN,
+        LogLevel::Info => Level::INFO,
+        LogLevel::Debug => Level::DEBUG,
+        LogLevel::Trace => Level::TRACE,
+    };
+    logging::init(level, args.log_format == LogFormat::Json);
 
-    let mut connection = PgConnection::establish(&database_url)
-        .unwrap_or_else(|_| panic!("Error connecting to {}", database_url));
+    let result = match &args.command {
+        Command::Load(load_args) => load(load_args, args.log_format),
+        Command::Migrate(args) => migrate(args),
+        Command::Stats(args) => stats(args),
+        Command::RefreshViews(args) => refresh_views(args),
+        Command::Verify(args) => verify(args),
+        Command::CheckReferences(args) => check_references(args),
+        Command::Export(args) => export(args),
+    };
 
-    connection
-        .transaction(|connection| {
-            // Load the badges XML file into the database.
-            let badges_path = args
-                .badges_path
-                .to_str()
-                .expect("Please provide a valid badges file path.");
-
-            load_badges(connection, badges_path)
-                .unwrap_or_else(|e| panic!("Failed to load badges: {}", e));
-
-            // Load the comments XML file into the database.
-            let comments_path = args
-                .comments_path
-                .to_str()
-                .expect("Please provide a valid comments file path.");
-
-            load_comments(connection, comments_path)
-                .unwrap_or_else(|e| panic!("Failed to load comments: {}", e));
-
-            // Load the posts XML file into the database.
-            let posts_path = args
-                .posts_path
-                .to_str()
-                .expect("Please provide a valid posts file path.");
-
-            load_posts(connection, posts_path)
-                .unwrap_or_else(|e| panic!("Failed to load posts: {}", e));
-
-            // Load the post history XML file into the database.
-            let post_history_path = args
-                .post_history_path
-                .to_str()
-                .expect("Please provide a valid post history file path.");
-
-            load_post_history(connection, post_history_path)
-                .unwrap_or_else(|e| panic!("Failed to load post history: {}", e));
-
-            // Load the post links XML file into the database.
-            let post_links_path = args
-                .post_links_path
-                .to_str()
-                .expect("Please provide a valid post links file path.");
-
-            load_post_links(connection, post_links_path)
-                .unwrap_or_else(|e| panic!("Failed to load post links: {}", e));
-
-            // Load the tags XML file into the database.
-            let tags_path = args
-                .tags_path
-                .to_str()
-                .expect("Please provide a valid tags file path.");
-
-            load_tags(connection, tags_path)
-                .unwrap_or_else(|e| panic!("Failed to load tags: {}", e));
-
-            // Load the users XML file into the database.
-            let users_path = args
-                .users_path
-                .to_str()
-                .expect("Please provide a valid users file path.");
-
-            load_users(connection, users_path)
-                .unwrap_or_else(|e| panic!("Failed to load users: {}", e));
-
-            // Load the votes XML file into the database.
-            let votes_path = args
-                .votes_path
-                .to_str()
-                .expect("Please provide a valid votes file path.");
-
-            load_votes(connection, votes_path)
-                .unwrap_or_else(|e| panic!("Failed to load votes: {}", e));
-
-            // Commit the transaction.
-            diesel::result::QueryResult::Ok(())
-        })
-        .unwrap_or_else(|_| panic!("Error loading data into the database"));
+    // Report the error once, without a backtrace, and exit with its code.
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            error!("{}", error);
+            error.exit_code()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use diesel::{prelude::*, sql_query, sql_types::Bool};
 
-    println!("Data loaded successfully into the database.");
+    use super::{
+        IdSet, LoadOptions, Tag, dump_file_key, handle_removed_rows, migrations, parse_percentage,
+        staging,
+    };
+
+    #[derive(Parser)]
+    struct LoadOptionsParser {
+        #[command(flatten)]
+        options: LoadOptions,
+    }
+
+    #[derive(QueryableByName)]
+    struct Check {
+        #[diesel(sql_type = Bool)]
+        check: bool,
+    }
+
+    fn check(connection: &mut PgConnection, query: &str) -> bool {
+        sql_query(query)
+            .get_result::<Check>(connection)
+            .unwrap()
+            .check
+    }
+
+    #[test]
+    fn dump_file_key_keeps_xml_files() {
+        assert_eq!(dump_file_key("Posts.xml"), ("posts.xml".to_string(), false));
+    }
+
+    #[test]
+    fn dump_file_key_strips_gzip() {
+        assert_eq!(
+            dump_file_key("Posts.xml.gz"),
+            ("posts.xml".to_string(), true)
+        );
+    }
+
+    #[test]
+    fn dump_file_key_strips_zstandard() {
+        assert_eq!(
+            dump_file_key("Posts.xml.zst"),
+            ("posts.xml".to_string(), true)
+        );
+    }
+
+    #[test]
+    fn dump_file_key_strips_bzip2() {
+        assert_eq!(
+            dump_file_key("Posts.xml.bz2"),
+            ("posts.xml".to_string(), true)
+        );
+    }
+
+    #[test]
+    fn dump_file_key_strips_7z() {
+        assert_eq!(
+            dump_file_key("Posts.xml.7z"),
+            ("posts.xml".to_string(), true)
+        );
+        assert_eq!(dump_file_key("Posts.7z"), ("posts.xml".to_string(), true));
+    }
+
+    #[test]
+    fn dump_file_key_strips_site_names() {
+        assert_eq!(
+            dump_file_key("stackoverflow.com-Posts.7z"),
+            ("posts.xml".to_string(), true)
+        );
+        assert_eq!(
+            dump_file_key("stackoverflow.com-PostHistory.xml.gz"),
+            ("posthistory.xml".to_string(), true)
+        );
+    }
+
+    #[test]
+    fn parse_percentage_accepts_0_to_100() {
+        assert_eq!(parse_percentage("0"), Ok(0.0));
+        assert_eq!(parse_percentage("2.5"), Ok(2.5));
+        assert_eq!(parse_percentage("100"), Ok(100.0));
+    }
+
+    #[test]
+    fn parse_percentage_rejects_values_out_of_range() {
+        for value in ["-0.1", "100.5", "NaN", "inf"] {
+            assert_eq!(
+                parse_percentage(value),
+                Err(format!("{} is not a percentage between 0 and 100", value))
+            );
+        }
+    }
+
+    #[test]
+    fn parse_percentage_rejects_values_that_are_not_numbers() {
+        for value in ["", "5%", "five"] {
+            assert!(parse_percentage(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a PostgreSQL database in STACKXCHG2PGSQL_TEST_DATABASE_URL"]
+    fn staging_marks_the_rows_removed_from_the_live_table() {
+        let database_url = std::env::var("STACKXCHG2PGSQL_TEST_DATABASE_URL")
+            .expect("STACKXCHG2PGSQL_TEST_DATABASE_URL is not set");
+        let mut connection = PgConnection::establish(&database_url).unwrap();
+        connection.begin_test_transaction().unwrap();
+        migrations::run_pending(&mut connection).unwrap();
+
+        // Tag 2 was removed from the new file, and tag 3 from an older one
+        for statement in [
+            "DELETE FROM tags",
+            "INSERT INTO tags (id, tag_name, count, removed_at) VALUES \
+             (1, 'rust', 10, NULL), (2, 'perl', 5, NULL), (3, 'cobol', 1, '2020-01-01')",
+        ] {
+            sql_query(statement).execute(&mut connection).unwrap();
+        }
+        staging::create_schema(&mut connection).unwrap();
+        staging::create_table(&mut connection, "tags").unwrap();
+        staging::use_staging(&mut connection).unwrap();
+        sql_query("INSERT INTO tags (id, tag_name, count) VALUES (1, 'rust', 20)")
+            .execute(&mut connection)
+            .unwrap();
+
+        let mut seen = IdSet::default();
+        seen.insert(1);
+        let options =
+            LoadOptionsParser::parse_from(["load", "--staging", "--removed-rows", "mark"]).options;
+        handle_removed_rows::<Tag>(&mut connection, &seen, 0, &options).unwrap();
+
+        assert!(check(
+            &mut connection,
+            "SELECT to_regclass('tags') = 'staging.tags'::regclass AS check"
+        ));
+        assert!(check(
+            &mut connection,
+            "SELECT array_agg(id ORDER BY id) = '{1,2,3}' AS check FROM staging.tags"
+        ));
+        assert!(check(
+            &mut connection,
+            "SELECT count = 20 AND removed_at IS NULL AS check FROM staging.tags WHERE id = 1"
+        ));
+        assert!(check(
+            &mut connection,
+            "SELECT count = 5 AND removed_at IS NOT NULL AS check FROM staging.tags WHERE id = 2"
+        ));
+        assert!(check(
+            &mut connection,
+            "SELECT removed_at = '2020-01-01' AS check FROM staging.tags WHERE id = 3"
+        ));
+    }
 }
@@ -0,0 +1,70 @@
+use diesel::{PgConnection, migration::MigrationVersion};
+use diesel_migrations::{EmbeddedMigrations, MigrationHarness, embed_migrations};
+
+use crate::errors::EtlError;
+
+/// The migrations of the `migrations` directory, embedded at build time.
+pub(crate) const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+/// The migration versions of a database, in the order they run.
+///
+/// # Fields
+///
+/// * `applied` - The versions already applied to the database
+/// * `pending` - The embedded versions not applied yet
+pub(crate) struct MigrationStatus {
+    pub applied: Vec<String>,
+    pub pending: Vec<String>,
+}
+
+/// Lists the applied and pending migrations of a database.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+///
+/// # Returns
+///
+/// * `Ok(MigrationStatus)` - The applied and pending versions
+/// * `Err(EtlError)` - If the migration table cannot be read
+pub(crate) fn status(connection: &mut PgConnection) -> Result<MigrationStatus, EtlError> {
+    let pending = connection
+        .pending_migrations(MIGRATIONS)
+        .map_err(EtlError::Migration)?
+        .iter()
+        .map(|migration| migration.name().version().to_string())
+        .collect();
+
+    let mut applied: Vec<String> = connection
+        .applied_migrations()
+        .map_err(EtlError::Migration)?
+        .iter()
+        .map(MigrationVersion::to_string)
+        .collect();
+
+    // Versions are timestamps, so they sort in the order they ran.
+    applied.sort();
+
+    Ok(MigrationStatus { applied, pending })
+}
+
+/// Runs every pending migration, each in its own transaction.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+///
+/// # Returns
+///
+/// * `Ok(Vec<String>)` - The versions that were applied, possibly none
+/// * `Err(EtlError)` - If a migration fails
+pub(crate) fn run_pending(connection: &mut PgConnection) -> Result<Vec<String>, EtlError> {
+    let versions = connection
+        .run_pending_migrations(MIGRATIONS)
+        .map_err(EtlError::Migration)?
+        .iter()
+        .map(MigrationVersion::to_string)
+        .collect();
+
+    Ok(versions)
+}
@@ -1,7 +1,10 @@
 use chrono::NaiveDateTime;
+use diesel::pg::CopyFormat;
 use diesel::prelude::*;
+use quick_xml::events::attributes::Attribute;
 
-use super::XmlModel;
+use super::{Loadable, XmlModel, attribute_value};
+use crate::{copy::CopyRow, errors::EtlError};
 
 #[derive(Clone, Debug, Default, Insertable)]
 #[diesel(table_name = crate::schema::badges)]
@@ -26,28 +29,80 @@ pub struct Badge {
 }
 
 impl XmlModel for Badge {
-    fn from_xml_element(element: &quick_xml::events::BytesStart) -> Result<Self, super::XmlError> {
-        let mut badge = Badge::default();
-
-        for attr in element.attributes() {
-            let attr = match attr {
-                Ok(attr) => attr,
-                Err(error) => return Err(super::XmlError::XmlParse(error.into())),
-            };
-
-            match attr.key.as_ref() {
-                b"Id" => badge.id = String::from_utf8(attr.value.to_vec())?.parse()?,
-                b"UserId" => badge.user_id = String::from_utf8(attr.value.to_vec())?.parse()?,
-                b"Name" => badge.name = String::from_utf8(attr.value.to_vec())?,
-                b"Date" => {
-                    let s = String::from_utf8(attr.value.to_vec())?;
-                    badge.date = NaiveDateTime::parse_from_str(&s, "%Y-%m-%dT%H:%M:%S%.f")?;
-                }
-                b"Class" => badge.class = String::from_utf8(attr.value.to_vec())?.parse()?,
-                b"TagBased" => badge.tag_based = String::from_utf8(attr.value.to_vec())? == "1",
-                _ => {}
+    const FILE_NAME: &'static str = "Badges.xml";
+
+    fn set_attribute(&mut self, attr: &Attribute) -> Result<(), super::XmlError> {
+        match attr.key.as_ref() {
+            b"Id" => self.id = attribute_value(attr)?.parse()?,
+            b"UserId" => self.user_id = attribute_value(attr)?.parse()?,
+            b"Name" => self.name = attribute_value(attr)?,
+            b"Date" => {
+                let s = attribute_value(attr)?;
+                self.date = NaiveDateTime::parse_from_str(&s, "%Y-%m-%dT%H:%M:%S%.f")?;
             }
+            b"Class" => self.class = attribute_value(attr)?.parse()?,
+            b"TagBased" => self.tag_based = attribute_value(attr)? == "1",
+            _ => {}
         }
-        Ok(badge)
+        Ok(())
+    }
+}
+
+impl Loadable for Badge {
+    const TABLE_NAME: &'static str = "badges";
+    const COLUMN_NAMES: &'static [&'static str] =
+        &["id", "user_id", "name", "date", "class", "tag_based"];
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn insert(rows: &[Self], connection: &mut PgConnection) -> QueryResult<usize> {
+        diesel::insert_into(crate::schema::badges::table)
+            .values(rows)
+            .execute(connection)
+    }
+
+    fn copy_from<F>(
+        connection: &mut PgConnection,
+        format: CopyFormat,
+        callback: F,
+    ) -> Result<usize, EtlError>
+    where
+        F: Fn(&mut dyn std::io::Write) -> Result<(), EtlError>,
+    {
+        use crate::schema::badges::dsl::*;
+
+        // Every column but `removed_at`, which the XML files do not have.
+        diesel::copy_from(badges)
+            .from_raw_data((id, user_id, name, date, class, tag_based), callback)
+            .with_format(format)
+            .execute(connection)
+    }
+
+    fn copy_to<F>(
+        connection: &mut PgConnection,
+        format: CopyFormat,
+        callback: F,
+    ) -> Result<(), EtlError>
+    where
+        F: FnOnce(&mut dyn std::io::BufRead) -> Result<(), EtlError>,
+    {
+        use crate::schema::badges::dsl::*;
+
+        let mut rows = diesel::copy_to((id, user_id, name, date, class, tag_based, removed_at))
+            .with_format(format)
+            .load_raw(connection)?;
+
+        callback(&mut rows)
+    }
+
+    fn write_copy_row(&self, row: &mut CopyRow) {
+        row.field(&self.id);
+        row.field(&self.user_id);
+        row.field(&self.name);
+        row.field(&self.date);
+        row.field(&self.class);
+        row.field(&self.tag_based);
     }
 }
@@ -1,7 +1,10 @@
 use chrono::NaiveDateTime;
+use diesel::pg::CopyFormat;
 use diesel::prelude::*;
+use quick_xml::events::attributes::Attribute;
 
-use super::XmlModel;
+use super::{Loadable, XmlModel, attribute_value};
+use crate::{copy::CopyRow, errors::EtlError};
 
 #[derive(Clone, Debug, Default, Insertable)]
 #[diesel(table_name = crate::schema::comments)]
@@ -30,40 +33,115 @@ pub struct Comment {
 }
 
 impl XmlModel for Comment {
-    fn from_xml_element(element: &quick_xml::events::BytesStart) -> Result<Self, super::XmlError> {
-        let mut comment = Comment::default();
+    const FILE_NAME: &'static str = "Comments.xml";
 
-        for attr in element.attributes() {
-            let attr = match attr {
-                Ok(attr) => attr,
-                Err(error) => return Err(super::XmlError::XmlParse(error.into())),
-            };
-
-            match attr.key.as_ref() {
-                b"Id" => comment.id = String::from_utf8(attr.value.to_vec())?.parse()?,
-                b"PostId" => {
-                    comment.post_id = Some(String::from_utf8(attr.value.to_vec())?.parse()?)
-                }
-                b"Score" => comment.score = Some(String::from_utf8(attr.value.to_vec())?.parse()?),
-                b"Text" => comment.text = String::from_utf8(attr.value.to_vec())?,
-                b"CreationDate" => {
-                    comment.creation_date = NaiveDateTime::parse_from_str(
-                        &String::from_utf8(attr.value.to_vec())?,
-                        "%Y-%m-%dT%H:%M:%S%.f",
-                    )?
-                }
-                b"UserDisplayName" => {
-                    comment.user_display_name = Some(String::from_utf8(attr.value.to_vec())?)
-                }
-                b"UserId" => {
-                    comment.user_id = Some(String::from_utf8(attr.value.to_vec())?.parse()?)
-                }
-                b"ContentLicense" => {
-                    comment.content_license = Some(String::from_utf8(attr.value.to_vec())?)
-                }
-                _ => {}
+    fn set_attribute(&mut self, attr: &Attribute) -> Result<(), super::XmlError> {
+        match attr.key.as_ref() {
+            b"Id" => self.id = attribute_value(attr)?.parse()?,
+            b"PostId" => self.post_id = Some(attribute_value(attr)?.parse()?),
+            b"Score" => self.score = Some(attribute_value(attr)?.parse()?),
+            b"Text" => self.text = attribute_value(attr)?,
+            b"CreationDate" => {
+                self.creation_date =
+                    NaiveDateTime::parse_from_str(&attribute_value(attr)?, "%Y-%m-%dT%H:%M:%S%.f")?
             }
+            b"UserDisplayName" => self.user_display_name = Some(attribute_value(attr)?),
+            b"UserId" => self.user_id = Some(attribute_value(attr)?.parse()?),
+            b"ContentLicense" => self.content_license = Some(attribute_value(attr)?),
+            _ => {}
         }
-        Ok(comment)
+        Ok(())
+    }
+}
+
+impl Loadable for Comment {
+    const TABLE_NAME: &'static str = "comments";
+    const COLUMN_NAMES: &'static [&'static str] = &[
+        "id",
+        "post_id",
+        "score",
+        "text",
+        "creation_date",
+        "user_display_name",
+        "user_id",
+        "content_license",
+    ];
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn insert(rows: &[Self], connection: &mut PgConnection) -> QueryResult<usize> {
+        diesel::insert_into(crate::schema::comments::table)
+            .values(rows)
+            .execute(connection)
+    }
+
+    fn copy_from<F>(
+        connection: &mut PgConnection,
+        format: CopyFormat,
+        callback: F,
+    ) -> Result<usize, EtlError>
+    where
+        F: Fn(&mut dyn std::io::Write) -> Result<(), EtlError>,
+    {
+        use crate::schema::comments::dsl::*;
+
+        // Every column but `removed_at`, which the XML files do not have.
+        diesel::copy_from(comments)
+            .from_raw_data(
+                (
+                    id,
+                    post_id,
+                    score,
+                    text,
+                    creation_date,
+                    user_display_name,
+                    user_id,
+                    content_license,
+                ),
+                callback,
+            )
+            .with_format(format)
+            .execute(connection)
+    }
+
+    fn copy_to<F>(
+        connection: &mut PgConnection,
+        format: CopyFormat,
+        callback: F,
+    ) -> Result<(), EtlError>
+    where
+        F: FnOnce(&mut dyn std::io::BufRead) -> Result<(), EtlError>,
+    {
+        use crate::schema::comments::dsl::*;
+
+        let mut rows = diesel::copy_to((
+            id,
+            post_id,
+            score,
+            text,
+            creation_date,
+            user_display_name,
+            user_id,
+            content_license,
+            removed_at,
+        ))
+        .with_format(format)
+        .load_raw(connection)?;
+
+        callback(&mut rows)
+    }
+
+    fn write_copy_row(&self, row: &mut CopyRow) {
+        row.field(&self.id);
+        row.field(&self.post_id);
+        // COPY does not apply column defaults, so mirror `DEFAULT 0` here.
+        row.field(&self.score.or(Some(0)));
+        row.field(&self.text);
+        row.field(&self.creation_date);
+        row.field(&self.user_display_name);
+        row.field(&self.user_id);
+        row.field(&self.content_license);
     }
 }
@@ -1,15 +1,26 @@
 mod badge;
 mod comment;
+mod parallel;
 mod post;
 mod tag;
 mod user;
 mod vote;
 
-use std::{fmt::Debug, fs::File, io::BufReader};
+use diesel::{PgConnection, QueryResult, pg::CopyFormat};
+use quick_xml::events::{BytesStart, attributes::Attribute};
+use std::{borrow::Cow, fmt::Debug, io::BufRead, path::Path, sync::Arc};
 use thiserror::Error;
+use tracing::debug;
+
+use crate::{
+    copy::CopyRow,
+    errors::EtlError,
+    source::{BytesRead, XmlSource},
+};
 
 pub use badge::Badge;
 pub use comment::Comment;
+pub(crate) use parallel::ParallelXmlModelIterator;
 pub use post::{Post, PostHistory, PostLink};
 pub use tag::Tag;
 pub use user::User;
@@ -30,7 +41,24 @@ pub use vote::Vote;
 ///
 /// Returns an `XmlError` if the XML element cannot be properly parsed or
 /// if it contains invalid data for the target type.
-pub(crate) trait XmlModel {
+pub(crate) trait XmlModel: Default {
+    /// The name of the XML file holding the model in a Stack Exchange dump.
+    const FILE_NAME: &'static str;
+
+    /// Sets the field matching an attribute of a `<row/>` element.
+    ///
+    /// Attributes without a matching field are ignored.
+    ///
+    /// # Parameters
+    ///
+    /// * `attr` - The attribute to set
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the attribute was set or ignored
+    /// * `Err(XmlError)` - If the value of the attribute is invalid
+    fn set_attribute(&mut self, attr: &Attribute) -> Result<(), XmlError>;
+
     /// Constructs a new instance from an XML element.
     ///
     /// # Parameters
@@ -40,22 +68,171 @@ pub(crate) trait XmlModel {
     /// # Returns
     ///
     /// * `Ok(Self)` - A successfully parsed instance
-    /// * `Err(XmlError)` - If parsing fails
-    fn from_xml_element(element: &quick_xml::events::BytesStart) -> Result<Self, XmlError>
+    /// * `Err(XmlError)` - If parsing fails, naming the attribute at fault
+    fn from_xml_element(element: &BytesStart) -> Result<Self, XmlError> {
+        let mut model = Self::default();
+
+        for attr in element.attributes() {
+            let attr = attr.map_err(|error| XmlError::XmlParse(error.into()))?;
+
+            model
+                .set_attribute(&attr)
+                .map_err(|error| XmlError::Attribute {
+                    name: String::from_utf8_lossy(attr.key.as_ref()).into_owned(),
+                    value: String::from_utf8_lossy(&attr.value).into_owned(),
+                    source: Box::new(error),
+                })?;
+        }
+
+        Ok(model)
+    }
+}
+
+/// Decodes the value of an XML attribute.
+///
+/// Entity references (`&lt;`, `&amp;`, ...) and character references
+/// (`&#xA;`, `&#10;`, ...) are replaced by the characters they stand for, so
+/// the stored text matches what the site displays.
+///
+/// # Parameters
+///
+/// * `attr` - The attribute to decode
+///
+/// # Returns
+///
+/// * `Ok(String)` - The decoded value
+/// * `Err(XmlError)` - If the value is not valid UTF-8 or contains an unknown reference
+pub(crate) fn attribute_value(attr: &Attribute) -> Result<String, XmlError> {
+    let value = String::from_utf8(attr.value.to_vec())?;
+
+    // Avoid a second copy for the common case of values without references.
+    let unescaped = quick_xml::escape::unescape(&value)?;
+    if let Cow::Owned(unescaped) = unescaped {
+        return Ok(unescaped);
+    }
+
+    Ok(value)
+}
+
+/// The maximum number of bind parameters PostgreSQL accepts in a single
+/// statement.
+pub(crate) const MAX_BIND_PARAMETERS: usize = 65535;
+
+/// A trait for models that can be written to their PostgreSQL table.
+///
+/// Implementors own the table-specific Diesel statements, so the loaders in
+/// `main.rs` can be written once for every table.
+pub(crate) trait Loadable: XmlModel + Debug + Sized {
+    /// The name of the table the model is stored in.
+    const TABLE_NAME: &'static str;
+
+    /// The columns written for every row, in table order.
+    const COLUMN_NAMES: &'static [&'static str];
+
+    /// The number of columns written for every row, i.e. the number of
+    /// bind parameters a single row can use in an `INSERT` statement.
+    const COLUMN_COUNT: usize = Self::COLUMN_NAMES.len();
+
+    /// Returns the largest number of rows a single `INSERT` statement can
+    /// hold without exceeding the bind parameter limit of PostgreSQL.
+    fn max_batch_size() -> usize {
+        MAX_BIND_PARAMETERS / Self::COLUMN_COUNT
+    }
+
+    /// Returns the `Id` of the row, as found in the XML file.
+    fn id(&self) -> i32;
+
+    /// Inserts the models with a single multi-row `INSERT` statement.
+    ///
+    /// # Parameters
+    ///
+    /// * `rows` - The models to insert, at most `max_batch_size()` of them
+    /// * `connection` - A mutable reference to the PostgreSQL database connection
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(usize)` - The number of rows inserted
+    /// * `Err(diesel::result::Error)` - If the insert fails
+    fn insert(rows: &[Self], connection: &mut PgConnection) -> QueryResult<usize>;
+
+    /// Runs a `COPY ... FROM STDIN` into the table of the model.
+    ///
+    /// # Parameters
+    ///
+    /// * `connection` - A mutable reference to the PostgreSQL database connection
+    /// * `format` - The format of the data written by `callback`
+    /// * `callback` - Writes the rows to the `COPY` stream
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(usize)` - The number of rows copied
+    /// * `Err(EtlError)` - If the statement or the callback fails
+    fn copy_from<F>(
+        connection: &mut PgConnection,
+        format: CopyFormat,
+        callback: F,
+    ) -> Result<usize, EtlError>
+    where
+        F: Fn(&mut dyn std::io::Write) -> Result<(), EtlError>;
+
+    /// Runs a `COPY ... TO STDOUT` of the table of the model.
+    ///
+    /// The rows hold the columns written by `write_copy_row`, followed by
+    /// `removed_at`, without a header line. The connection cannot run other
+    /// statements afterwards.
+    ///
+    /// # Parameters
+    ///
+    /// * `connection` - A mutable reference to the PostgreSQL database connection
+    /// * `format` - The format of the data read by `callback`
+    /// * `callback` - Reads the rows from the `COPY` stream
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If every row was read
+    /// * `Err(EtlError)` - If the statement or the callback fails
+    fn copy_to<F>(
+        connection: &mut PgConnection,
+        format: CopyFormat,
+        callback: F,
+    ) -> Result<(), EtlError>
     where
-        Self: Sized;
+        F: FnOnce(&mut dyn BufRead) -> Result<(), EtlError>;
+
+    /// Writes the columns of the model to a `COPY` row, in table order.
+    ///
+    /// # Parameters
+    ///
+    /// * `row` - The row to append the columns to
+    fn write_copy_row(&self, row: &mut CopyRow);
 }
+
+/// The tables of every model, in load order.
+pub(crate) const TABLE_NAMES: [&str; 8] = [
+    Badge::TABLE_NAME,
+    Comment::TABLE_NAME,
+    Post::TABLE_NAME,
+    PostHistory::TABLE_NAME,
+    PostLink::TABLE_NAME,
+    Tag::TABLE_NAME,
+    User::TABLE_NAME,
+    Vote::TABLE_NAME,
+];
+
 /// Implements the `Iterator` trait for parsing XML content.
 ///
 /// # Type Parameters
 ///
 /// * `T`: A type that can be constructed from XML elements using `from_xml_element`
-pub(crate) struct XmlModelIterator<T>
+/// * `R`: The buffered source the XML is read from
+pub(crate) struct XmlModelIterator<T, R = XmlSource>
 where
     T: XmlModel + Debug,
+    R: BufRead,
 {
-    reader: quick_xml::Reader<BufReader<File>>,
+    reader: quick_xml::Reader<R>,
     buffer: Vec<u8>,
+    failed: bool,
     _phantom: std::marker::PhantomData<T>,
 }
 
@@ -65,42 +242,88 @@ where
 {
     /// Creates a new `XmlModelIterator` from a file path.
     ///
+    /// Compressed files are decompressed while they are read.
+    ///
     /// # Parameters
     ///
     /// * `source` - A string slice representing the path to the XML file
+    /// * `bytes` - Counts the bytes read from the file
     ///
     /// # Returns
     ///
     /// * `Ok(XmlModelIterator<T>)` - A new iterator instance
-    /// * `Err(XmlError)` - If the file cannot be opened or read
-    pub(crate) fn new(source: &str) -> Result<Self, quick_xml::Error> {
-        let mut reader = quick_xml::Reader::from_file(source)?;
+    /// * `Err(io::Error)` - If the file cannot be opened or read
+    pub(crate) fn new(source: &str, bytes: Arc<BytesRead>) -> std::io::Result<Self> {
+        let source = crate::source::open(Path::new(source), T::FILE_NAME, bytes)?;
+
+        Ok(Self::from_reader(source))
+    }
+}
+
+impl<T, R> XmlModelIterator<T, R>
+where
+    T: XmlModel + Debug,
+    R: BufRead,
+{
+    /// Creates a new `XmlModelIterator` reading from a buffered source.
+    ///
+    /// # Parameters
+    ///
+    /// * `source` - The buffered source to read the XML from
+    ///
+    /// # Returns
+    ///
+    /// * `XmlModelIterator<T, R>` - A new iterator instance
+    pub(crate) fn from_reader(source: R) -> Self {
+        let mut reader = quick_xml::Reader::from_reader(source);
         reader.config_mut().trim_text(true);
 
-        Ok(Self {
+        Self {
             reader,
             buffer: Vec::new(),
+            failed: false,
             _phantom: std::marker::PhantomData,
-        })
+        }
     }
 }
 
-impl<T> Iterator for XmlModelIterator<T>
+impl<T, R> Iterator for XmlModelIterator<T, R>
 where
     T: XmlModel + Debug,
+    R: BufRead,
 {
-    type Item = Result<T, XmlError>;
+    type Item = Result<T, RowError>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
         loop {
             self.buffer.clear();
 
             match self.reader.read_event_into(&mut self.buffer) {
-                Err(error) => return Some(Err(XmlError::from(error))),
-                Ok(quick_xml::events::Event::Eof) => return None,
+                Err(error) => {
+                    // I/O errors, e.g. from a truncated archive, would be
+                    // returned again by every following read.
+                    self.failed = matches!(error, quick_xml::Error::Io(_));
+                    return Some(Err(RowError::read(&self.reader, error)));
+                }
+                Ok(quick_xml::events::Event::Eof) => {
+                    debug!(
+                        bytes = self.reader.buffer_position(),
+                        "Read the end of the XML file"
+                    );
+                    return None;
+                }
                 Ok(quick_xml::events::Event::Empty(element)) => {
                     if element.name().as_ref() == b"row" {
-                        return Some(T::from_xml_element(&element));
+                        let position = row_position(&self.reader, &element);
+
+                        return Some(
+                            T::from_xml_element(&element)
+                                .map_err(|error| RowError::parse(position, &element, error)),
+                        );
                     }
                 }
                 _ => continue,
@@ -109,17 +332,243 @@ where
     }
 }
 
+/// Returns the byte offset of a `<row/>` element that was just read.
+///
+/// # Parameters
+///
+/// * `reader` - The reader the element was read from
+/// * `element` - The element, without its `<` and `/>` delimiters
+///
+/// # Returns
+///
+/// * `u64` - The offset of the `<` of the element in the decompressed XML
+pub(crate) fn row_position<R>(reader: &quick_xml::Reader<R>, element: &BytesStart) -> u64 {
+    reader.buffer_position() - element.len() as u64 - 3
+}
+
+/// A row of an XML file that could not be read or parsed.
+///
+/// # Fields
+///
+/// * `position` - The byte offset of the row or the read error in the decompressed XML
+/// * `id` - The `Id` attribute of the row, if it has a valid one
+/// * `row` - The `<row/>` element as found in the file, if it could be read
+/// * `error` - What went wrong
+#[derive(Debug)]
+pub struct RowError {
+    pub position: u64,
+    pub id: Option<i32>,
+    pub row: Option<String>,
+    pub error: XmlError,
+}
+
+impl RowError {
+    /// Creates the error of a `<row/>` element that could not be parsed.
+    ///
+    /// # Parameters
+    ///
+    /// * `position` - The byte offset of the element, from `row_position`
+    /// * `element` - The element that could not be parsed
+    /// * `error` - Why the element could not be parsed
+    ///
+    /// # Returns
+    ///
+    /// * `RowError` - The error, with the `Id` of the row if it is valid
+    pub(crate) fn parse(position: u64, element: &BytesStart, error: XmlError) -> Self {
+        let id = element
+            .try_get_attribute("Id")
+            .ok()
+            .flatten()
+            .and_then(|attr| std::str::from_utf8(&attr.value).ok()?.parse().ok());
+
+        Self {
+            position,
+            id,
+            row: Some(format!("<{}/>", String::from_utf8_lossy(element))),
+            error,
+        }
+    }
+
+    /// Creates the error of a read that failed between rows.
+    ///
+    /// # Parameters
+    ///
+    /// * `reader` - The reader that failed
+    /// * `error` - The error returned by the reader
+    ///
+    /// # Returns
+    ///
+    /// * `RowError` - The error, positioned where the reader stopped
+    pub(crate) fn read<R>(reader: &quick_xml::Reader<R>, error: quick_xml::Error) -> Self {
+        // Syntax errors record where they start, I/O errors happen at the end
+        // of what was read.
+        let position = match error {
+            quick_xml::Error::Io(_) => reader.buffer_position(),
+            _ => reader.error_position(),
+        };
+
+        Self {
+            position,
+            id: None,
+            row: None,
+            error: XmlError::from(error),
+        }
+    }
+}
+
+impl std::fmt::Display for RowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.id {
+            Some(id) => write!(f, "row {} at byte {}: {}", id, self.position, self.error),
+            None => write!(f, "byte {}: {}", self.position, self.error),
+        }
+    }
+}
+
+impl std::error::Error for RowError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum XmlError {
     #[error("XML parsing error: {0}")]
     XmlParse(#[from] quick_xml::Error),
 
+    #[error("Invalid {name} {value:?}: {source}")]
+    Attribute {
+        name: String,
+        value: String,
+        source: Box<XmlError>,
+    },
+
     #[error("UTF-8 conversion error: {0}")]
     Utf8Conversion(#[from] std::string::FromUtf8Error),
 
+    #[error("XML unescape error: {0}")]
+    Unescape(#[from] quick_xml::escape::EscapeError),
+
     #[error("Integer parse error: {0}")]
     IntegerParse(#[from] std::num::ParseIntError),
 
     #[error("Date parsing error: {0}")]
     DateParse(#[from] chrono::ParseError),
 }
+
+impl XmlError {
+    /// Returns the name and raw value of the attribute at fault, if the
+    /// error is about a single attribute.
+    pub(crate) fn attribute(&self) -> Option<(&str, &str)> {
+        match self {
+            XmlError::Attribute { name, value, .. } => Some((name, value)),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the variant of the error, e.g. `IntegerParse`,
+    /// looking through the attribute at fault.
+    pub(crate) fn reason(&self) -> &'static str {
+        match self {
+            XmlError::XmlParse(_) => "XmlParse",
+            XmlError::Attribute { source, .. } => source.reason(),
+            XmlError::Utf8Conversion(_) => "Utf8Conversion",
+            XmlError::Unescape(_) => "Unescape",
+            XmlError::IntegerParse(_) => "IntegerParse",
+            XmlError::DateParse(_) => "DateParse",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::{Compression, write::GzEncoder};
+    use quick_xml::{events::attributes::Attribute, name::QName};
+    use std::{borrow::Cow, io::Write, sync::Arc};
+
+    use super::{Badge, XmlError, XmlModelIterator, attribute_value};
+    use crate::source::BytesRead;
+
+    fn attribute(value: &[u8]) -> Attribute<'_> {
+        Attribute {
+            key: QName(b"Body"),
+            value: Cow::Borrowed(value),
+        }
+    }
+
+    #[test]
+    fn attribute_value_keeps_plain_text() {
+        let value = attribute_value(&attribute(b"plain text")).unwrap();
+
+        assert_eq!(value, "plain text");
+    }
+
+    #[test]
+    fn attribute_value_decodes_entity_references() {
+        let value = attribute_value(&attribute(b"&lt;p&gt;a &amp; b&quot;&lt;/p&gt;")).unwrap();
+
+        assert_eq!(value, "<p>a & b\"</p>");
+    }
+
+    #[test]
+    fn attribute_value_decodes_character_references() {
+        let value = attribute_value(&attribute(b"it&#x27;s&#xA;line&#10;end")).unwrap();
+
+        assert_eq!(value, "it's\nline\nend");
+    }
+
+    #[test]
+    fn attribute_value_rejects_unknown_entities() {
+        let error = attribute_value(&attribute(b"a &bogus; b")).unwrap_err();
+
+        assert!(matches!(error, XmlError::Unescape(_)), "{:?}", error);
+    }
+
+    #[test]
+    fn attribute_value_rejects_invalid_utf8() {
+        let error = attribute_value(&attribute(b"\xff")).unwrap_err();
+
+        assert!(matches!(error, XmlError::Utf8Conversion(_)), "{:?}", error);
+    }
+
+    #[test]
+    fn iterator_fails_on_a_truncated_gzip() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        writeln!(
+            encoder,
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<badges>"
+        )
+        .unwrap();
+
+        for id in 1..=10_000 {
+            writeln!(
+                encoder,
+                "  <row Id=\"{}\" UserId=\"{}\" Name=\"Autobiographer\" \
+                 Date=\"2010-07-20T19:07:22.990\" Class=\"3\" TagBased=\"False\" />",
+                id,
+                id * 7 % 1000
+            )
+            .unwrap();
+        }
+
+        writeln!(encoder, "</badges>").unwrap();
+        let archive = encoder.finish().unwrap();
+
+        let path = std::env::temp_dir().join(format!("truncated-{}.xml.gz", std::process::id()));
+        std::fs::write(&path, &archive[..archive.len() / 2]).unwrap();
+
+        let rows: Vec<_> =
+            XmlModelIterator::<Badge>::new(path.to_str().unwrap(), Arc::new(BytesRead::default()))
+                .unwrap()
+                .collect();
+        std::fs::remove_file(&path).unwrap();
+
+        let (last, parsed) = rows.split_last().unwrap();
+        assert!(!parsed.is_empty());
+        assert!(parsed.iter().all(Result::is_ok));
+
+        let error = last.as_ref().unwrap_err();
+        assert!(error.row.is_none(), "{:?}", error);
+        assert!(matches!(error.error, XmlError::XmlParse(_)), "{:?}", error);
+    }
+}
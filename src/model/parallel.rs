@@ -0,0 +1,242 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    io::BufRead,
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver, SyncSender},
+    },
+    thread,
+};
+
+use quick_xml::events::{BytesStart, Event};
+use tracing::{Span, debug};
+
+use super::{RowError, XmlModel, row_position};
+use crate::source::BytesRead;
+
+/// The number of `<row/>` elements handed to a parser thread at once.
+const CHUNK_SIZE: usize = 1000;
+
+/// A chunk of raw `<row/>` elements and their byte offsets, numbered in
+/// file order.
+///
+/// A read error ends the chunk it occurred in, after its rows.
+struct RawChunk {
+    sequence: usize,
+    rows: Vec<(u64, BytesStart<'static>)>,
+    error: Option<RowError>,
+}
+
+/// A chunk of parsed models, numbered in file order.
+type ParsedChunk<T> = (usize, Vec<Result<T, RowError>>);
+
+/// Parses the rows of an XML file on several threads.
+///
+/// A reader thread splits the `<row/>` elements of the file into chunks,
+/// which a pool of parser threads turns into models. The chunks are put
+/// back in file order, so the models are yielded in the same order as by
+/// `XmlModelIterator`.
+///
+/// # Type Parameters
+///
+/// * `T`: A type that can be constructed from XML elements using `from_xml_element`
+pub(crate) struct ParallelXmlModelIterator<T>
+where
+    T: XmlModel + Debug + Send + 'static,
+{
+    parsed: Receiver<ParsedChunk<T>>,
+    pending: BTreeMap<usize, Vec<Result<T, RowError>>>,
+    next_sequence: usize,
+    current: std::vec::IntoIter<Result<T, RowError>>,
+}
+
+impl<T> ParallelXmlModelIterator<T>
+where
+    T: XmlModel + Debug + Send + 'static,
+{
+    /// Creates a new `ParallelXmlModelIterator` from a file path.
+    ///
+    /// Compressed files are decompressed while they are read.
+    ///
+    /// # Parameters
+    ///
+    /// * `source` - A string slice representing the path to the XML file
+    /// * `threads` - The number of parser threads
+    /// * `bytes` - Counts the bytes read from the file
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ParallelXmlModelIterator<T>)` - A new iterator instance
+    /// * `Err(io::Error)` - If the file cannot be opened or read
+    pub(crate) fn new(
+        source: &str,
+        threads: usize,
+        bytes: Arc<BytesRead>,
+    ) -> std::io::Result<Self> {
+        let source = crate::source::open(Path::new(source), T::FILE_NAME, bytes)?;
+
+        // Bounded channels keep the reader at most a few chunks ahead of the
+        // parsers, and the parsers ahead of the database.
+        let (raw_sender, raw_receiver) = mpsc::sync_channel(threads * 2);
+        let (parsed_sender, parsed_receiver) = mpsc::sync_channel(threads * 2);
+        let raw_receiver = Arc::new(Mutex::new(raw_receiver));
+
+        debug!(
+            threads,
+            chunk_size = CHUNK_SIZE,
+            "Parsing rows on parser threads"
+        );
+
+        // The reader logs in the span of the table being loaded.
+        let span = Span::current();
+        thread::spawn(move || span.in_scope(|| read_chunks(source, raw_sender)));
+
+        for _ in 0..threads {
+            let raw_receiver = Arc::clone(&raw_receiver);
+            let parsed_sender = parsed_sender.clone();
+
+            thread::spawn(move || parse_chunks::<T>(&raw_receiver, &parsed_sender));
+        }
+
+        Ok(Self {
+            parsed: parsed_receiver,
+            pending: BTreeMap::new(),
+            next_sequence: 0,
+            current: Vec::new().into_iter(),
+        })
+    }
+}
+
+impl<T> Iterator for ParallelXmlModelIterator<T>
+where
+    T: XmlModel + Debug + Send + 'static,
+{
+    type Item = Result<T, RowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.current.next() {
+                return Some(row);
+            }
+
+            // Wait for the next chunk in file order, holding back the ones
+            // parsed ahead of it.
+            while !self.pending.contains_key(&self.next_sequence) {
+                let (sequence, rows) = self.parsed.recv().ok()?;
+                self.pending.insert(sequence, rows);
+            }
+
+            let rows = self.pending.remove(&self.next_sequence)?;
+            self.current = rows.into_iter();
+            self.next_sequence += 1;
+        }
+    }
+}
+
+/// Splits the `<row/>` elements of an XML source into chunks.
+///
+/// Returns once the source is exhausted, on an I/O error, or when the
+/// parser threads are gone because the iterator was dropped.
+///
+/// # Parameters
+///
+/// * `source` - The buffered source to read the XML from
+/// * `chunks` - The channel the chunks are sent to
+fn read_chunks<R>(source: R, chunks: SyncSender<RawChunk>)
+where
+    R: BufRead,
+{
+    let mut reader = quick_xml::Reader::from_reader(source);
+    reader.config_mut().trim_text(true);
+
+    let mut buffer = Vec::new();
+    let mut chunk = RawChunk {
+        sequence: 0,
+        rows: Vec::with_capacity(CHUNK_SIZE),
+        error: None,
+    };
+
+    loop {
+        buffer.clear();
+
+        let finished = match reader.read_event_into(&mut buffer) {
+            Err(error) => {
+                // Like `XmlModelIterator`, stop after an I/O error, which
+                // would be returned again by every following read.
+                let fatal = matches!(error, quick_xml::Error::Io(_));
+                chunk.error = Some(RowError::read(&reader, error));
+                fatal
+            }
+            Ok(Event::Eof) => true,
+            Ok(Event::Empty(element)) => {
+                if element.name().as_ref() == b"row" {
+                    let position = row_position(&reader, &element);
+                    chunk.rows.push((position, element.into_owned()));
+                }
+                false
+            }
+            _ => false,
+        };
+
+        if finished || chunk.error.is_some() || chunk.rows.len() == CHUNK_SIZE {
+            let next = RawChunk {
+                sequence: chunk.sequence + 1,
+                rows: Vec::with_capacity(CHUNK_SIZE),
+                error: None,
+            };
+
+            if chunks.send(std::mem::replace(&mut chunk, next)).is_err() {
+                return;
+            }
+
+            if finished {
+                debug!(chunks = chunk.sequence, "Split the XML file into chunks");
+                return;
+            }
+        }
+    }
+}
+
+/// Parses chunks of raw `<row/>` elements into models until no chunks are
+/// left.
+///
+/// # Parameters
+///
+/// * `chunks` - The channel the raw chunks are received from, shared by the parser threads
+/// * `parsed` - The channel the parsed chunks are sent to
+fn parse_chunks<T>(chunks: &Mutex<Receiver<RawChunk>>, parsed: &SyncSender<ParsedChunk<T>>)
+where
+    T: XmlModel + Debug + Send + 'static,
+{
+    loop {
+        // Release the lock before parsing, so the other threads can take
+        // the next chunk.
+        let chunk = match chunks.lock() {
+            Ok(chunks) => chunks.recv(),
+            Err(_) => return,
+        };
+
+        let Ok(chunk) = chunk else {
+            return;
+        };
+
+        let mut rows: Vec<_> = chunk
+            .rows
+            .iter()
+            .map(|(position, element)| {
+                T::from_xml_element(element)
+                    .map_err(|error| RowError::parse(*position, element, error))
+            })
+            .collect();
+
+        if let Some(error) = chunk.error {
+            rows.push(Err(error));
+        }
+
+        if parsed.send((chunk.sequence, rows)).is_err() {
+            return;
+        }
+    }
+}
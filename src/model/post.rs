@@ -1,7 +1,10 @@
 use chrono::NaiveDateTime;
+use diesel::pg::CopyFormat;
 use diesel::prelude::*;
+use quick_xml::events::attributes::Attribute;
 
-use super::XmlModel;
+use super::{Loadable, XmlModel, attribute_value};
+use crate::{copy::CopyRow, errors::EtlError};
 
 #[derive(Clone, Debug, Default, Insertable)]
 #[diesel(table_name = crate::schema::posts)]
@@ -58,95 +61,215 @@ pub struct Post {
 }
 
 impl XmlModel for Post {
-    fn from_xml_element(element: &quick_xml::events::BytesStart) -> Result<Self, super::XmlError> {
-        let mut post = Post::default();
-
-        for attr in element.attributes() {
-            let attr = match attr {
-                Ok(attr) => attr,
-                Err(error) => return Err(super::XmlError::XmlParse(error.into())),
-            };
-
-            match attr.key.as_ref() {
-                b"Id" => post.id = String::from_utf8(attr.value.to_vec())?.parse()?,
-                b"PostTypeId" => {
-                    post.post_type_id = Some(String::from_utf8(attr.value.to_vec())?.parse()?)
-                }
-                b"AcceptedAnswerId" => {
-                    post.accepted_answer_id = Some(String::from_utf8(attr.value.to_vec())?.parse()?)
-                }
-                b"CreationDate" => {
-                    post.creation_date = Some(NaiveDateTime::parse_from_str(
-                        &String::from_utf8(attr.value.to_vec())?,
-                        "%Y-%m-%dT%H:%M:%S%.f",
-                    )?)
-                }
-                b"DeletionDate" => {
-                    post.deletion_date = Some(NaiveDateTime::parse_from_str(
-                        &String::from_utf8(attr.value.to_vec())?,
-                        "%Y-%m-%dT%H:%M:%S%.f",
-                    )?)
-                }
-                b"Score" => post.score = Some(String::from_utf8(attr.value.to_vec())?.parse()?),
-                b"ViewCount" => {
-                    post.view_count = Some(String::from_utf8(attr.value.to_vec())?.parse()?)
-                }
-                b"Body" => post.body = Some(String::from_utf8(attr.value.to_vec())?),
-                b"OwnerUserId" => {
-                    post.owner_user_id = Some(String::from_utf8(attr.value.to_vec())?.parse()?)
-                }
-                b"OwnerDisplayName" => {
-                    post.owner_display_name = Some(String::from_utf8(attr.value.to_vec())?)
-                }
-                b"LastEditorUserId" => {
-                    post.last_editor_user_id =
-                        Some(String::from_utf8(attr.value.to_vec())?.parse()?)
-                }
-                b"LastEditorDisplayName" => {
-                    post.last_editor_display_name = Some(String::from_utf8(attr.value.to_vec())?)
-                }
-                b"LastEditDate" => {
-                    post.last_edit_date = Some(NaiveDateTime::parse_from_str(
-                        &String::from_utf8(attr.value.to_vec())?,
-                        "%Y-%m-%dT%H:%M:%S%.f",
-                    )?)
-                }
-                b"LastActivityDate" => {
-                    post.last_activity_date = Some(NaiveDateTime::parse_from_str(
-                        &String::from_utf8(attr.value.to_vec())?,
-                        "%Y-%m-%dT%H:%M:%S%.f",
-                    )?)
-                }
-                b"Title" => post.title = Some(String::from_utf8(attr.value.to_vec())?),
-                b"Tags" => post.tags = Some(String::from_utf8(attr.value.to_vec())?),
-                b"AnswerCount" => {
-                    post.answer_count = Some(String::from_utf8(attr.value.to_vec())?.parse()?)
-                }
-                b"CommentCount" => {
-                    post.comment_count = Some(String::from_utf8(attr.value.to_vec())?.parse()?)
-                }
-                b"FavoriteCount" => {
-                    post.favorite_count = Some(String::from_utf8(attr.value.to_vec())?.parse()?)
-                }
-                b"CloseDate" => {
-                    post.close_date = Some(NaiveDateTime::parse_from_str(
-                        &String::from_utf8(attr.value.to_vec())?,
-                        "%Y-%m-%dT%H:%M:%S%.f",
-                    )?)
-                }
-                b"CommunityOwnedDate" => {
-                    post.community_owned_date = Some(NaiveDateTime::parse_from_str(
-                        &String::from_utf8(attr.value.to_vec())?,
-                        "%Y-%m-%dT%H:%M:%S%.f",
-                    )?)
-                }
-                b"ContentLicense" => {
-                    post.content_license = Some(String::from_utf8(attr.value.to_vec())?)
-                }
-                _ => {}
+    const FILE_NAME: &'static str = "Posts.xml";
+
+    fn set_attribute(&mut self, attr: &Attribute) -> Result<(), super::XmlError> {
+        match attr.key.as_ref() {
+            b"Id" => self.id = attribute_value(attr)?.parse()?,
+            b"PostTypeId" => self.post_type_id = Some(attribute_value(attr)?.parse()?),
+            b"AcceptedAnswerId" => self.accepted_answer_id = Some(attribute_value(attr)?.parse()?),
+            b"CreationDate" => {
+                self.creation_date = Some(NaiveDateTime::parse_from_str(
+                    &attribute_value(attr)?,
+                    "%Y-%m-%dT%H:%M:%S%.f",
+                )?)
+            }
+            b"DeletionDate" => {
+                self.deletion_date = Some(NaiveDateTime::parse_from_str(
+                    &attribute_value(attr)?,
+                    "%Y-%m-%dT%H:%M:%S%.f",
+                )?)
+            }
+            b"Score" => self.score = Some(attribute_value(attr)?.parse()?),
+            b"ViewCount" => self.view_count = Some(attribute_value(attr)?.parse()?),
+            b"Body" => self.body = Some(attribute_value(attr)?),
+            b"OwnerUserId" => self.owner_user_id = Some(attribute_value(attr)?.parse()?),
+            b"OwnerDisplayName" => self.owner_display_name = Some(attribute_value(attr)?),
+            b"LastEditorUserId" => self.last_editor_user_id = Some(attribute_value(attr)?.parse()?),
+            b"LastEditorDisplayName" => {
+                self.last_editor_display_name = Some(attribute_value(attr)?)
+            }
+            b"LastEditDate" => {
+                self.last_edit_date = Some(NaiveDateTime::parse_from_str(
+                    &attribute_value(attr)?,
+                    "%Y-%m-%dT%H:%M:%S%.f",
+                )?)
+            }
+            b"LastActivityDate" => {
+                self.last_activity_date = Some(NaiveDateTime::parse_from_str(
+                    &attribute_value(attr)?,
+                    "%Y-%m-%dT%H:%M:%S%.f",
+                )?)
+            }
+            b"Title" => self.title = Some(attribute_value(attr)?),
+            b"Tags" => self.tags = Some(attribute_value(attr)?),
+            b"AnswerCount" => self.answer_count = Some(attribute_value(attr)?.parse()?),
+            b"CommentCount" => self.comment_count = Some(attribute_value(attr)?.parse()?),
+            b"FavoriteCount" => self.favorite_count = Some(attribute_value(attr)?.parse()?),
+            b"CloseDate" => {
+                self.close_date = Some(NaiveDateTime::parse_from_str(
+                    &attribute_value(attr)?,
+                    "%Y-%m-%dT%H:%M:%S%.f",
+                )?)
+            }
+            b"CommunityOwnedDate" => {
+                self.community_owned_date = Some(NaiveDateTime::parse_from_str(
+                    &attribute_value(attr)?,
+                    "%Y-%m-%dT%H:%M:%S%.f",
+                )?)
             }
+            b"ContentLicense" => self.content_license = Some(attribute_value(attr)?),
+            _ => {}
         }
-        Ok(post)
+        Ok(())
+    }
+}
+
+impl Loadable for Post {
+    const TABLE_NAME: &'static str = "posts";
+    const COLUMN_NAMES: &'static [&'static str] = &[
+        "id",
+        "post_type_id",
+        "accepted_answer_id",
+        "creation_date",
+        "deletion_date",
+        "score",
+        "view_count",
+        "body",
+        "owner_user_id",
+        "owner_display_name",
+        "last_editor_user_id",
+        "last_editor_display_name",
+        "last_edit_date",
+        "last_activity_date",
+        "title",
+        "tags",
+        "answer_count",
+        "comment_count",
+        "favorite_count",
+        "close_date",
+        "community_owned_date",
+        "content_license",
+    ];
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn insert(rows: &[Self], connection: &mut PgConnection) -> QueryResult<usize> {
+        diesel::insert_into(crate::schema::posts::table)
+            .values(rows)
+            .execute(connection)
+    }
+
+    fn copy_from<F>(
+        connection: &mut PgConnection,
+        format: CopyFormat,
+        callback: F,
+    ) -> Result<usize, EtlError>
+    where
+        F: Fn(&mut dyn std::io::Write) -> Result<(), EtlError>,
+    {
+        use crate::schema::posts::dsl::*;
+
+        // Every column but `removed_at`, which the XML files do not have.
+        diesel::copy_from(posts)
+            .from_raw_data(
+                (
+                    id,
+                    post_type_id,
+                    accepted_answer_id,
+                    creation_date,
+                    deletion_date,
+                    score,
+                    view_count,
+                    body,
+                    owner_user_id,
+                    owner_display_name,
+                    last_editor_user_id,
+                    last_editor_display_name,
+                    last_edit_date,
+                    last_activity_date,
+                    title,
+                    tags,
+                    answer_count,
+                    comment_count,
+                    favorite_count,
+                    close_date,
+                    community_owned_date,
+                    content_license,
+                ),
+                callback,
+            )
+            .with_format(format)
+            .execute(connection)
+    }
+
+    fn copy_to<F>(
+        connection: &mut PgConnection,
+        format: CopyFormat,
+        callback: F,
+    ) -> Result<(), EtlError>
+    where
+        F: FnOnce(&mut dyn std::io::BufRead) -> Result<(), EtlError>,
+    {
+        use crate::schema::posts::dsl::*;
+
+        let mut rows = diesel::copy_to((
+            id,
+            post_type_id,
+            accepted_answer_id,
+            creation_date,
+            deletion_date,
+            score,
+            view_count,
+            body,
+            owner_user_id,
+            owner_display_name,
+            last_editor_user_id,
+            last_editor_display_name,
+            last_edit_date,
+            last_activity_date,
+            title,
+            tags,
+            answer_count,
+            comment_count,
+            favorite_count,
+            close_date,
+            community_owned_date,
+            content_license,
+            removed_at,
+        ))
+        .with_format(format)
+        .load_raw(connection)?;
+
+        callback(&mut rows)
+    }
+
+    fn write_copy_row(&self, row: &mut CopyRow) {
+        row.field(&self.id);
+        row.field(&self.post_type_id);
+        row.field(&self.accepted_answer_id);
+        row.field(&self.creation_date);
+        row.field(&self.deletion_date);
+        // COPY does not apply column defaults, so mirror `DEFAULT 0` here.
+        row.field(&self.score.or(Some(0)));
+        row.field(&self.view_count.or(Some(0)));
+        row.field(&self.body);
+        row.field(&self.owner_user_id);
+        row.field(&self.owner_display_name);
+        row.field(&self.last_editor_user_id);
+        row.field(&self.last_editor_display_name);
+        row.field(&self.last_edit_date);
+        row.field(&self.last_activity_date);
+        row.field(&self.title);
+        row.field(&self.tags);
+        row.field(&self.answer_count.or(Some(0)));
+        row.field(&self.comment_count.or(Some(0)));
+        row.field(&self.favorite_count.or(Some(0)));
+        row.field(&self.close_date);
+        row.field(&self.community_owned_date);
+        row.field(&self.content_license);
     }
 }
 
@@ -180,48 +303,129 @@ pub struct PostHistory {
 }
 
 impl XmlModel for PostHistory {
-    fn from_xml_element(element: &quick_xml::events::BytesStart) -> Result<Self, super::XmlError> {
-        let mut post_history = PostHistory::default();
-
-        for attr in element.attributes() {
-            let attr = match attr {
-                Ok(attr) => attr,
-                Err(error) => return Err(super::XmlError::XmlParse(error.into())),
-            };
-
-            match attr.key.as_ref() {
-                b"Id" => post_history.id = String::from_utf8(attr.value.to_vec())?.parse()?,
-                b"PostHistoryTypeId" => {
-                    post_history.post_history_type_id =
-                        Some(String::from_utf8(attr.value.to_vec())?.parse()?)
-                }
-                b"PostId" => {
-                    post_history.post_id = Some(String::from_utf8(attr.value.to_vec())?.parse()?)
-                }
-                b"RevisionGUID" => {
-                    post_history.revision_guid = Some(String::from_utf8(attr.value.to_vec())?)
-                }
-                b"CreationDate" => {
-                    post_history.creation_date = Some(NaiveDateTime::parse_from_str(
-                        &String::from_utf8(attr.value.to_vec())?,
-                        "%Y-%m-%dT%H:%M:%S%.f",
-                    )?)
-                }
-                b"UserId" => {
-                    post_history.user_id = Some(String::from_utf8(attr.value.to_vec())?.parse()?)
-                }
-                b"UserDisplayName" => {
-                    post_history.user_display_name = Some(String::from_utf8(attr.value.to_vec())?)
-                }
-                b"Comment" => post_history.comment = Some(String::from_utf8(attr.value.to_vec())?),
-                b"Text" => post_history.text = Some(String::from_utf8(attr.value.to_vec())?),
-                b"ContentLicense" => {
-                    post_history.content_license = Some(String::from_utf8(attr.value.to_vec())?)
-                }
-                _ => {}
+    const FILE_NAME: &'static str = "PostHistory.xml";
+
+    fn set_attribute(&mut self, attr: &Attribute) -> Result<(), super::XmlError> {
+        match attr.key.as_ref() {
+            b"Id" => self.id = attribute_value(attr)?.parse()?,
+            b"PostHistoryTypeId" => {
+                self.post_history_type_id = Some(attribute_value(attr)?.parse()?)
             }
+            b"PostId" => self.post_id = Some(attribute_value(attr)?.parse()?),
+            b"RevisionGUID" => self.revision_guid = Some(attribute_value(attr)?),
+            b"CreationDate" => {
+                self.creation_date = Some(NaiveDateTime::parse_from_str(
+                    &attribute_value(attr)?,
+                    "%Y-%m-%dT%H:%M:%S%.f",
+                )?)
+            }
+            b"UserId" => self.user_id = Some(attribute_value(attr)?.parse()?),
+            b"UserDisplayName" => self.user_display_name = Some(attribute_value(attr)?),
+            b"Comment" => self.comment = Some(attribute_value(attr)?),
+            b"Text" => self.text = Some(attribute_value(attr)?),
+            b"ContentLicense" => self.content_license = Some(attribute_value(attr)?),
+            _ => {}
         }
-        Ok(post_history)
+        Ok(())
+    }
+}
+
+impl Loadable for PostHistory {
+    const TABLE_NAME: &'static str = "post_historys";
+    const COLUMN_NAMES: &'static [&'static str] = &[
+        "id",
+        "post_history_type_id",
+        "post_id",
+        "revision_guid",
+        "creation_date",
+        "user_id",
+        "user_display_name",
+        "comment",
+        "text",
+        "content_license",
+    ];
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn insert(rows: &[Self], connection: &mut PgConnection) -> QueryResult<usize> {
+        diesel::insert_into(crate::schema::post_historys::table)
+            .values(rows)
+            .execute(connection)
+    }
+
+    fn copy_from<F>(
+        connection: &mut PgConnection,
+        format: CopyFormat,
+        callback: F,
+    ) -> Result<usize, EtlError>
+    where
+        F: Fn(&mut dyn std::io::Write) -> Result<(), EtlError>,
+    {
+        use crate::schema::post_historys::dsl::*;
+
+        // Every column but `removed_at`, which the XML files do not have.
+        diesel::copy_from(post_historys)
+            .from_raw_data(
+                (
+                    id,
+                    post_history_type_id,
+                    post_id,
+                    revision_guid,
+                    creation_date,
+                    user_id,
+                    user_display_name,
+                    comment,
+                    text,
+                    content_license,
+                ),
+                callback,
+            )
+            .with_format(format)
+            .execute(connection)
+    }
+
+    fn copy_to<F>(
+        connection: &mut PgConnection,
+        format: CopyFormat,
+        callback: F,
+    ) -> Result<(), EtlError>
+    where
+        F: FnOnce(&mut dyn std::io::BufRead) -> Result<(), EtlError>,
+    {
+        use crate::schema::post_historys::dsl::*;
+
+        let mut rows = diesel::copy_to((
+            id,
+            post_history_type_id,
+            post_id,
+            revision_guid,
+            creation_date,
+            user_id,
+            user_display_name,
+            comment,
+            text,
+            content_license,
+            removed_at,
+        ))
+        .with_format(format)
+        .load_raw(connection)?;
+
+        callback(&mut rows)
+    }
+
+    fn write_copy_row(&self, row: &mut CopyRow) {
+        row.field(&self.id);
+        row.field(&self.post_history_type_id);
+        row.field(&self.post_id);
+        row.field(&self.revision_guid);
+        row.field(&self.creation_date);
+        row.field(&self.user_id);
+        row.field(&self.user_display_name);
+        row.field(&self.comment);
+        row.field(&self.text);
+        row.field(&self.content_license);
     }
 }
 
@@ -245,33 +449,93 @@ pub struct PostLink {
 }
 
 impl XmlModel for PostLink {
-    fn from_xml_element(element: &quick_xml::events::BytesStart) -> Result<Self, super::XmlError> {
-        let mut post_link = PostLink::default();
-
-        for attr in element.attributes() {
-            let attr = match attr {
-                Ok(attr) => attr,
-                Err(error) => return Err(super::XmlError::XmlParse(error.into())),
-            };
-
-            match attr.key.as_ref() {
-                b"Id" => post_link.id = String::from_utf8(attr.value.to_vec())?.parse()?,
-                b"CreationDate" => {
-                    post_link.creation_date = NaiveDateTime::parse_from_str(
-                        &String::from_utf8(attr.value.to_vec())?,
-                        "%Y-%m-%dT%H:%M:%S%.f",
-                    )?
-                }
-                b"PostId" => post_link.post_id = String::from_utf8(attr.value.to_vec())?.parse()?,
-                b"RelatedPostId" => {
-                    post_link.related_post_id = String::from_utf8(attr.value.to_vec())?.parse()?
-                }
-                b"LinkTypeId" => {
-                    post_link.link_type_id = String::from_utf8(attr.value.to_vec())?.parse()?
-                }
-                _ => {}
+    const FILE_NAME: &'static str = "PostLinks.xml";
+
+    fn set_attribute(&mut self, attr: &Attribute) -> Result<(), super::XmlError> {
+        match attr.key.as_ref() {
+            b"Id" => self.id = attribute_value(attr)?.parse()?,
+            b"CreationDate" => {
+                self.creation_date =
+                    NaiveDateTime::parse_from_str(&attribute_value(attr)?, "%Y-%m-%dT%H:%M:%S%.f")?
             }
+            b"PostId" => self.post_id = attribute_value(attr)?.parse()?,
+            b"RelatedPostId" => self.related_post_id = attribute_value(attr)?.parse()?,
+            b"LinkTypeId" => self.link_type_id = attribute_value(attr)?.parse()?,
+            _ => {}
         }
-        Ok(post_link)
+        Ok(())
+    }
+}
+
+impl Loadable for PostLink {
+    const TABLE_NAME: &'static str = "post_links";
+    const COLUMN_NAMES: &'static [&'static str] = &[
+        "id",
+        "creation_date",
+        "post_id",
+        "related_post_id",
+        "link_type_id",
+    ];
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn insert(rows: &[Self], connection: &mut PgConnection) -> QueryResult<usize> {
+        diesel::insert_into(crate::schema::post_links::table)
+            .values(rows)
+            .execute(connection)
+    }
+
+    fn copy_from<F>(
+        connection: &mut PgConnection,
+        format: CopyFormat,
+        callback: F,
+    ) -> Result<usize, EtlError>
+    where
+        F: Fn(&mut dyn std::io::Write) -> Result<(), EtlError>,
+    {
+        use crate::schema::post_links::dsl::*;
+
+        // Every column but `removed_at`, which the XML files do not have.
+        diesel::copy_from(post_links)
+            .from_raw_data(
+                (id, creation_date, post_id, related_post_id, link_type_id),
+                callback,
+            )
+            .with_format(format)
+            .execute(connection)
+    }
+
+    fn copy_to<F>(
+        connection: &mut PgConnection,
+        format: CopyFormat,
+        callback: F,
+    ) -> Result<(), EtlError>
+    where
+        F: FnOnce(&mut dyn std::io::BufRead) -> Result<(), EtlError>,
+    {
+        use crate::schema::post_links::dsl::*;
+
+        let mut rows = diesel::copy_to((
+            id,
+            creation_date,
+            post_id,
+            related_post_id,
+            link_type_id,
+            removed_at,
+        ))
+        .with_format(format)
+        .load_raw(connection)?;
+
+        callback(&mut rows)
+    }
+
+    fn write_copy_row(&self, row: &mut CopyRow) {
+        row.field(&self.id);
+        row.field(&self.creation_date);
+        row.field(&self.post_id);
+        row.field(&self.related_post_id);
+        row.field(&self.link_type_id);
     }
 }
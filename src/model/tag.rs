@@ -1,6 +1,9 @@
+use diesel::pg::CopyFormat;
 use diesel::prelude::*;
+use quick_xml::events::attributes::Attribute;
 
-use super::XmlModel;
+use super::{Loadable, XmlModel, attribute_value};
+use crate::{copy::CopyRow, errors::EtlError};
 
 #[derive(Clone, Debug, Default, Insertable)]
 #[diesel(table_name = crate::schema::tags)]
@@ -27,34 +30,107 @@ pub struct Tag {
 }
 
 impl XmlModel for Tag {
-    fn from_xml_element(element: &quick_xml::events::BytesStart) -> Result<Self, super::XmlError> {
-        let mut tag = Tag::default();
-
-        for attr in element.attributes() {
-            let attr = match attr {
-                Ok(attr) => attr,
-                Err(error) => return Err(super::XmlError::XmlParse(error.into())),
-            };
-
-            match attr.key.as_ref() {
-                b"Id" => tag.id = String::from_utf8(attr.value.to_vec())?.parse()?,
-                b"TagName" => tag.tag_name = Some(String::from_utf8(attr.value.to_vec())?),
-                b"Count" => tag.count = String::from_utf8(attr.value.to_vec())?.parse()?,
-                b"ExcerptPostId" => {
-                    tag.excerpt_post_id = Some(String::from_utf8(attr.value.to_vec())?.parse()?)
-                }
-                b"WikiPostId" => {
-                    tag.wiki_post_id = Some(String::from_utf8(attr.value.to_vec())?.parse()?)
-                }
-                b"IsModeratorOnly" => {
-                    tag.is_moderator_only = Some(String::from_utf8(attr.value.to_vec())?.parse()?)
-                }
-                b"IsRequired" => {
-                    tag.is_required = Some(String::from_utf8(attr.value.to_vec())?.parse()?)
-                }
-                _ => {}
-            }
+    const FILE_NAME: &'static str = "Tags.xml";
+
+    fn set_attribute(&mut self, attr: &Attribute) -> Result<(), super::XmlError> {
+        match attr.key.as_ref() {
+            b"Id" => self.id = attribute_value(attr)?.parse()?,
+            b"TagName" => self.tag_name = Some(attribute_value(attr)?),
+            b"Count" => self.count = attribute_value(attr)?.parse()?,
+            b"ExcerptPostId" => self.excerpt_post_id = Some(attribute_value(attr)?.parse()?),
+            b"WikiPostId" => self.wiki_post_id = Some(attribute_value(attr)?.parse()?),
+            b"IsModeratorOnly" => self.is_moderator_only = Some(attribute_value(attr)?.parse()?),
+            b"IsRequired" => self.is_required = Some(attribute_value(attr)?.parse()?),
+            _ => {}
         }
-        Ok(tag)
+        Ok(())
+    }
+}
+
+impl Loadable for Tag {
+    const TABLE_NAME: &'static str = "tags";
+    const COLUMN_NAMES: &'static [&'static str] = &[
+        "id",
+        "tag_name",
+        "count",
+        "excerpt_post_id",
+        "wiki_post_id",
+        "is_moderator_only",
+        "is_required",
+    ];
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn insert(rows: &[Self], connection: &mut PgConnection) -> QueryResult<usize> {
+        diesel::insert_into(crate::schema::tags::table)
+            .values(rows)
+            .execute(connection)
+    }
+
+    fn copy_from<F>(
+        connection: &mut PgConnection,
+        format: CopyFormat,
+        callback: F,
+    ) -> Result<usize, EtlError>
+    where
+        F: Fn(&mut dyn std::io::Write) -> Result<(), EtlError>,
+    {
+        use crate::schema::tags::dsl::*;
+
+        // Every column but `removed_at`, which the XML files do not have.
+        diesel::copy_from(tags)
+            .from_raw_data(
+                (
+                    id,
+                    tag_name,
+                    count,
+                    excerpt_post_id,
+                    wiki_post_id,
+                    is_moderator_only,
+                    is_required,
+                ),
+                callback,
+            )
+            .with_format(format)
+            .execute(connection)
+    }
+
+    fn copy_to<F>(
+        connection: &mut PgConnection,
+        format: CopyFormat,
+        callback: F,
+    ) -> Result<(), EtlError>
+    where
+        F: FnOnce(&mut dyn std::io::BufRead) -> Result<(), EtlError>,
+    {
+        use crate::schema::tags::dsl::*;
+
+        let mut rows = diesel::copy_to((
+            id,
+            tag_name,
+            count,
+            excerpt_post_id,
+            wiki_post_id,
+            is_moderator_only,
+            is_required,
+            removed_at,
+        ))
+        .with_format(format)
+        .load_raw(connection)?;
+
+        callback(&mut rows)
+    }
+
+    fn write_copy_row(&self, row: &mut CopyRow) {
+        row.field(&self.id);
+        row.field(&self.tag_name);
+        row.field(&self.count);
+        row.field(&self.excerpt_post_id);
+        row.field(&self.wiki_post_id);
+        // COPY does not apply column defaults, so mirror `DEFAULT 0` here.
+        row.field(&self.is_moderator_only.or(Some(0)));
+        row.field(&self.is_required.or(Some(0)));
     }
 }
@@ -1,7 +1,10 @@
 use chrono::NaiveDateTime;
+use diesel::pg::CopyFormat;
 use diesel::prelude::*;
+use quick_xml::events::attributes::Attribute;
 
-use super::XmlModel;
+use super::{Loadable, XmlModel, attribute_value};
+use crate::{copy::CopyRow, errors::EtlError};
 
 #[derive(Clone, Debug, Default, Insertable)]
 #[diesel(table_name = crate::schema::users)]
@@ -42,51 +45,150 @@ pub struct User {
 }
 
 impl XmlModel for User {
-    fn from_xml_element(element: &quick_xml::events::BytesStart) -> Result<Self, super::XmlError> {
-        let mut user = User::default();
+    const FILE_NAME: &'static str = "Users.xml";
 
-        for attr in element.attributes() {
-            let attr = match attr {
-                Ok(attr) => attr,
-                Err(error) => return Err(super::XmlError::XmlParse(error.into())),
-            };
-
-            match attr.key.as_ref() {
-                b"Id" => user.id = String::from_utf8(attr.value.to_vec())?.parse()?,
-                b"Reputation" => {
-                    user.reputation = String::from_utf8(attr.value.to_vec())?.parse()?
-                }
-                b"CreationDate" => {
-                    let date_str = String::from_utf8(attr.value.to_vec())?;
-                    user.creation_date =
-                        NaiveDateTime::parse_from_str(&date_str, "%Y-%m-%dT%H:%M:%S%.f")?;
-                }
-                b"DisplayName" => user.display_name = Some(String::from_utf8(attr.value.to_vec())?),
-                b"LastAccessDate" => {
-                    let date_str = String::from_utf8(attr.value.to_vec())?;
-                    user.last_access_date =
-                        NaiveDateTime::parse_from_str(&date_str, "%Y-%m-%dT%H:%M:%S%.f")?;
-                }
-                b"WebsiteUrl" => user.website_url = Some(String::from_utf8(attr.value.to_vec())?),
-                b"Location" => user.location = Some(String::from_utf8(attr.value.to_vec())?),
-                b"AboutMe" => user.about_me = Some(String::from_utf8(attr.value.to_vec())?),
-                b"Views" => user.views = Some(String::from_utf8(attr.value.to_vec())?.parse()?),
-                b"UpVotes" => {
-                    user.up_votes = Some(String::from_utf8(attr.value.to_vec())?.parse()?)
-                }
-                b"DownVotes" => {
-                    user.down_votes = Some(String::from_utf8(attr.value.to_vec())?.parse()?)
-                }
-                b"ProfileImageUrl" => {
-                    user.profile_image_url = Some(String::from_utf8(attr.value.to_vec())?)
-                }
-                b"EmailHash" => user.email_hash = Some(String::from_utf8(attr.value.to_vec())?),
-                b"AccountId" => {
-                    user.account_id = Some(String::from_utf8(attr.value.to_vec())?.parse()?)
-                }
-                _ => {}
+    fn set_attribute(&mut self, attr: &Attribute) -> Result<(), super::XmlError> {
+        match attr.key.as_ref() {
+            b"Id" => self.id = attribute_value(attr)?.parse()?,
+            b"Reputation" => self.reputation = attribute_value(attr)?.parse()?,
+            b"CreationDate" => {
+                let date_str = attribute_value(attr)?;
+                self.creation_date =
+                    NaiveDateTime::parse_from_str(&date_str, "%Y-%m-%dT%H:%M:%S%.f")?;
+            }
+            b"DisplayName" => self.display_name = Some(attribute_value(attr)?),
+            b"LastAccessDate" => {
+                let date_str = attribute_value(attr)?;
+                self.last_access_date =
+                    NaiveDateTime::parse_from_str(&date_str, "%Y-%m-%dT%H:%M:%S%.f")?;
             }
+            b"WebsiteUrl" => self.website_url = Some(attribute_value(attr)?),
+            b"Location" => self.location = Some(attribute_value(attr)?),
+            b"AboutMe" => self.about_me = Some(attribute_value(attr)?),
+            b"Views" => self.views = Some(attribute_value(attr)?.parse()?),
+            b"UpVotes" => self.up_votes = Some(attribute_value(attr)?.parse()?),
+            b"DownVotes" => self.down_votes = Some(attribute_value(attr)?.parse()?),
+            b"ProfileImageUrl" => self.profile_image_url = Some(attribute_value(attr)?),
+            b"EmailHash" => self.email_hash = Some(attribute_value(attr)?),
+            b"AccountId" => self.account_id = Some(attribute_value(attr)?.parse()?),
+            _ => {}
         }
-        Ok(user)
+        Ok(())
+    }
+}
+
+impl Loadable for User {
+    const TABLE_NAME: &'static str = "users";
+    const COLUMN_NAMES: &'static [&'static str] = &[
+        "id",
+        "reputation",
+        "creation_date",
+        "display_name",
+        "last_access_date",
+        "website_url",
+        "location",
+        "about_me",
+        "views",
+        "up_votes",
+        "down_votes",
+        "profile_image_url",
+        "email_hash",
+        "account_id",
+    ];
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn insert(rows: &[Self], connection: &mut PgConnection) -> QueryResult<usize> {
+        diesel::insert_into(crate::schema::users::table)
+            .values(rows)
+            .execute(connection)
+    }
+
+    fn copy_from<F>(
+        connection: &mut PgConnection,
+        format: CopyFormat,
+        callback: F,
+    ) -> Result<usize, EtlError>
+    where
+        F: Fn(&mut dyn std::io::Write) -> Result<(), EtlError>,
+    {
+        use crate::schema::users::dsl::*;
+
+        // Every column but `removed_at`, which the XML files do not have.
+        diesel::copy_from(users)
+            .from_raw_data(
+                (
+                    id,
+                    reputation,
+                    creation_date,
+                    display_name,
+                    last_access_date,
+                    website_url,
+                    location,
+                    about_me,
+                    views,
+                    up_votes,
+                    down_votes,
+                    profile_image_url,
+                    email_hash,
+                    account_id,
+                ),
+                callback,
+            )
+            .with_format(format)
+            .execute(connection)
+    }
+
+    fn copy_to<F>(
+        connection: &mut PgConnection,
+        format: CopyFormat,
+        callback: F,
+    ) -> Result<(), EtlError>
+    where
+        F: FnOnce(&mut dyn std::io::BufRead) -> Result<(), EtlError>,
+    {
+        use crate::schema::users::dsl::*;
+
+        let mut rows = diesel::copy_to((
+            id,
+            reputation,
+            creation_date,
+            display_name,
+            last_access_date,
+            website_url,
+            location,
+            about_me,
+            views,
+            up_votes,
+            down_votes,
+            profile_image_url,
+            email_hash,
+            account_id,
+            removed_at,
+        ))
+        .with_format(format)
+        .load_raw(connection)?;
+
+        callback(&mut rows)
+    }
+
+    fn write_copy_row(&self, row: &mut CopyRow) {
+        row.field(&self.id);
+        row.field(&self.reputation);
+        row.field(&self.creation_date);
+        row.field(&self.display_name);
+        row.field(&self.last_access_date);
+        row.field(&self.website_url);
+        row.field(&self.location);
+        row.field(&self.about_me);
+        // COPY does not apply column defaults, so mirror `DEFAULT 0` here.
+        row.field(&self.views.or(Some(0)));
+        row.field(&self.up_votes.or(Some(0)));
+        row.field(&self.down_votes.or(Some(0)));
+        row.field(&self.profile_image_url);
+        row.field(&self.email_hash);
+        row.field(&self.account_id);
     }
 }
@@ -1,7 +1,10 @@
 use chrono::NaiveDateTime;
+use diesel::pg::CopyFormat;
 use diesel::prelude::*;
+use quick_xml::events::attributes::Attribute;
 
-use super::XmlModel;
+use super::{Loadable, XmlModel, attribute_value};
+use crate::{copy::CopyRow, errors::EtlError};
 
 #[derive(Clone, Debug, Default, Insertable)]
 #[diesel(table_name = crate::schema::votes)]
@@ -26,34 +29,106 @@ pub struct Vote {
 }
 
 impl XmlModel for Vote {
-    fn from_xml_element(element: &quick_xml::events::BytesStart) -> Result<Self, super::XmlError> {
-        let mut vote = Vote::default();
-
-        for attr in element.attributes() {
-            let attr = match attr {
-                Ok(attr) => attr,
-                Err(error) => return Err(super::XmlError::XmlParse(error.into())),
-            };
-
-            match attr.key.as_ref() {
-                b"Id" => vote.id = String::from_utf8(attr.value.to_vec())?.parse()?,
-                b"PostId" => vote.post_id = String::from_utf8(attr.value.to_vec())?.parse()?,
-                b"VoteTypeId" => {
-                    vote.vote_type_id = String::from_utf8(attr.value.to_vec())?.parse()?
-                }
-                b"UserId" => vote.user_id = Some(String::from_utf8(attr.value.to_vec())?.parse()?),
-                b"CreationDate" => {
-                    vote.creation_date = Some(NaiveDateTime::parse_from_str(
-                        &String::from_utf8(attr.value.to_vec())?,
-                        "%Y-%m-%dT%H:%M:%S%.f",
-                    )?)
-                }
-                b"BountyAmount" => {
-                    vote.bounty_amount = Some(String::from_utf8(attr.value.to_vec())?.parse()?)
-                }
-                _ => {}
+    const FILE_NAME: &'static str = "Votes.xml";
+
+    fn set_attribute(&mut self, attr: &Attribute) -> Result<(), super::XmlError> {
+        match attr.key.as_ref() {
+            b"Id" => self.id = attribute_value(attr)?.parse()?,
+            b"PostId" => self.post_id = attribute_value(attr)?.parse()?,
+            b"VoteTypeId" => self.vote_type_id = attribute_value(attr)?.parse()?,
+            b"UserId" => self.user_id = Some(attribute_value(attr)?.parse()?),
+            b"CreationDate" => {
+                self.creation_date = Some(NaiveDateTime::parse_from_str(
+                    &attribute_value(attr)?,
+                    "%Y-%m-%dT%H:%M:%S%.f",
+                )?)
             }
+            b"BountyAmount" => self.bounty_amount = Some(attribute_value(attr)?.parse()?),
+            _ => {}
         }
-        Ok(vote)
+        Ok(())
+    }
+}
+
+impl Loadable for Vote {
+    const TABLE_NAME: &'static str = "votes";
+    const COLUMN_NAMES: &'static [&'static str] = &[
+        "id",
+        "post_id",
+        "vote_type_id",
+        "user_id",
+        "creation_date",
+        "bounty_amount",
+    ];
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn insert(rows: &[Self], connection: &mut PgConnection) -> QueryResult<usize> {
+        diesel::insert_into(crate::schema::votes::table)
+            .values(rows)
+            .execute(connection)
+    }
+
+    fn copy_from<F>(
+        connection: &mut PgConnection,
+        format: CopyFormat,
+        callback: F,
+    ) -> Result<usize, EtlError>
+    where
+        F: Fn(&mut dyn std::io::Write) -> Result<(), EtlError>,
+    {
+        use crate::schema::votes::dsl::*;
+
+        // Every column but `removed_at`, which the XML files do not have.
+        diesel::copy_from(votes)
+            .from_raw_data(
+                (
+                    id,
+                    post_id,
+                    vote_type_id,
+                    user_id,
+                    creation_date,
+                    bounty_amount,
+                ),
+                callback,
+            )
+            .with_format(format)
+            .execute(connection)
+    }
+
+    fn copy_to<F>(
+        connection: &mut PgConnection,
+        format: CopyFormat,
+        callback: F,
+    ) -> Result<(), EtlError>
+    where
+        F: FnOnce(&mut dyn std::io::BufRead) -> Result<(), EtlError>,
+    {
+        use crate::schema::votes::dsl::*;
+
+        let mut rows = diesel::copy_to((
+            id,
+            post_id,
+            vote_type_id,
+            user_id,
+            creation_date,
+            bounty_amount,
+            removed_at,
+        ))
+        .with_format(format)
+        .load_raw(connection)?;
+
+        callback(&mut rows)
+    }
+
+    fn write_copy_row(&self, row: &mut CopyRow) {
+        row.field(&self.id);
+        row.field(&self.post_id);
+        row.field(&self.vote_type_id);
+        row.field(&self.user_id);
+        row.field(&self.creation_date);
+        row.field(&self.bounty_amount);
     }
 }
@@ -0,0 +1,429 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use tracing::info;
+
+use crate::source::BytesRead;
+
+/// How often the progress bar is redrawn.
+const BAR_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The number of rows read between two checks of the clock.
+const ROWS_PER_TICK: u64 = 1024;
+
+/// The width of the bar drawn for a single table, in characters.
+const BAR_WIDTH: usize = 30;
+
+/// Whether a progress bar is drawn on the last line of standard error.
+static BAR_DRAWN: AtomicBool = AtomicBool::new(false);
+
+/// Erases the progress bar, if one is drawn, so the next line is not
+/// written after it.
+pub(crate) fn clear_bar() {
+    if BAR_DRAWN.swap(false, Ordering::Relaxed) {
+        eprint!("\r\x1b[2K");
+    }
+}
+
+/// How the progress of the tables being loaded is shown.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Style {
+    /// A single line redrawn in place, for terminals.
+    Bar,
+
+    /// A message per table being loaded, logged periodically.
+    Log,
+}
+
+/// The progress of the tables being loaded, shared by the jobs.
+///
+/// Every table counts the rows and bytes it reads, and whichever job notices
+/// that a report is due prints the progress of every table. The counts of
+/// the tables are kept once they are loaded, for the load report.
+pub(crate) struct Progress {
+    style: Option<Style>,
+    interval: Duration,
+    checksums: bool,
+    state: Mutex<State>,
+}
+
+/// The tables being loaded and the ones done, and when their progress was
+/// last shown.
+struct State {
+    tables: Vec<Arc<Counters>>,
+    finished: Vec<Arc<Counters>>,
+    reported: Instant,
+}
+
+/// What a table has read and written so far.
+struct Counters {
+    table: String,
+    bytes: Arc<BytesRead>,
+    rows: AtomicU64,
+    written: AtomicU64,
+    rejected: Mutex<BTreeMap<String, u64>>,
+    started: Instant,
+}
+
+/// The counts of a loaded table.
+///
+/// # Fields
+///
+/// * `bytes` - The bytes read from its XML file, with the size and checksum of the file
+/// * `rows` - The number of rows read from the XML file
+/// * `written` - The number of rows written to the table
+/// * `rejected` - The number of rejected rows, by reason
+pub(crate) struct TableCounts {
+    pub bytes: Arc<BytesRead>,
+    pub rows: u64,
+    pub written: u64,
+    pub rejected: BTreeMap<String, u64>,
+}
+
+impl Progress {
+    /// Creates the progress of a load, with no tables yet.
+    ///
+    /// # Parameters
+    ///
+    /// * `style` - How the progress is shown, or `None` to only count
+    /// * `interval` - The time between two log lines of a table
+    /// * `checksums` - Whether the checksums of the XML files are computed
+    ///
+    /// # Returns
+    ///
+    /// * `Progress` - The progress of the load
+    pub(crate) fn new(style: Option<Style>, interval: Duration, checksums: bool) -> Self {
+        let interval = match style {
+            Some(Style::Bar) => BAR_INTERVAL,
+            _ => interval,
+        };
+
+        Self {
+            style,
+            interval,
+            checksums,
+            state: Mutex::new(State {
+                tables: Vec::new(),
+                finished: Vec::new(),
+                reported: Instant::now(),
+            }),
+        }
+    }
+
+    /// Starts counting the rows and bytes read for a table.
+    ///
+    /// # Parameters
+    ///
+    /// * `table` - The table being loaded
+    ///
+    /// # Returns
+    ///
+    /// * `TableProgress` - The counters of the table, removed once dropped
+    pub(crate) fn start(&self, table: &str) -> TableProgress<'_> {
+        let bytes = match self.checksums {
+            true => BytesRead::with_checksum(),
+            false => BytesRead::default(),
+        };
+
+        let counters = Arc::new(Counters {
+            table: table.to_string(),
+            bytes: Arc::new(bytes),
+            rows: AtomicU64::new(0),
+            written: AtomicU64::new(0),
+            rejected: Mutex::default(),
+            started: Instant::now(),
+        });
+
+        self.lock().tables.push(Arc::clone(&counters));
+
+        TableProgress {
+            progress: self,
+            counters,
+        }
+    }
+
+    /// Shows the progress of every table if a report is due.
+    ///
+    /// A job finding another one reporting goes on with its rows.
+    fn tick(&self) {
+        let Some(style) = self.style else {
+            return;
+        };
+
+        let Ok(mut state) = self.state.try_lock() else {
+            return;
+        };
+
+        if state.reported.elapsed() < self.interval {
+            return;
+        }
+
+        state.reported = Instant::now();
+
+        match style {
+            Style::Bar => {
+                let line = match state.tables.as_slice() {
+                    [table] => table.bar(),
+                    tables => tables
+                        .iter()
+                        .map(|table| table.summary())
+                        .collect::<Vec<_>>()
+                        .join(" | "),
+                };
+
+                // Progress is best effort, a closed stderr must not fail the load.
+                let mut stderr = io::stderr().lock();
+                BAR_DRAWN.store(true, Ordering::Relaxed);
+                let _ = write!(stderr, "\r\x1b[2K{}", line).and_then(|_| stderr.flush());
+            }
+            Style::Log => state.tables.iter().for_each(|table| table.log()),
+        }
+    }
+
+    /// Returns the counts of a table once it is loaded, or failed.
+    ///
+    /// # Parameters
+    ///
+    /// * `table` - The table
+    ///
+    /// # Returns
+    ///
+    /// * `Option<TableCounts>` - The counts, or `None` if the table was not started
+    pub(crate) fn counts(&self, table: &str) -> Option<TableCounts> {
+        let state = self.lock();
+        let counters = state
+            .finished
+            .iter()
+            .find(|counters| counters.table == table)?;
+
+        Some(TableCounts {
+            bytes: Arc::clone(&counters.bytes),
+            rows: counters.rows.load(Ordering::Relaxed),
+            written: counters.written.load(Ordering::Relaxed),
+            rejected: counters
+                .rejected
+                .lock()
+                .unwrap_or_else(|error| error.into_inner())
+                .clone(),
+        })
+    }
+
+    /// Locks the state, even if a job panicked while holding it.
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+/// The progress of a table being loaded.
+pub(crate) struct TableProgress<'a> {
+    progress: &'a Progress,
+    counters: Arc<Counters>,
+}
+
+impl TableProgress<'_> {
+    /// Returns the counter of the bytes read from the XML file of the table.
+    pub(crate) fn bytes(&self) -> Arc<BytesRead> {
+        Arc::clone(&self.counters.bytes)
+    }
+
+    /// Counts a row read from the XML file, and shows the progress if a
+    /// report is due.
+    pub(crate) fn row(&self) {
+        let rows = self.counters.rows.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if rows.is_multiple_of(ROWS_PER_TICK) {
+            self.progress.tick();
+        }
+    }
+
+    /// Counts the rows written to the table, once they are committed.
+    ///
+    /// # Parameters
+    ///
+    /// * `rows` - The number of rows
+    pub(crate) fn written(&self, rows: usize) {
+        self.counters
+            .written
+            .fetch_add(rows as u64, Ordering::Relaxed);
+    }
+
+    /// Counts a rejected row.
+    ///
+    /// # Parameters
+    ///
+    /// * `reason` - Why the row was rejected
+    pub(crate) fn rejected(&self, reason: &str) {
+        *self
+            .counters
+            .rejected
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .entry(reason.to_string())
+            .or_default() += 1;
+    }
+
+    /// Logs how many rows were read from the XML file of the table, and how
+    /// fast.
+    pub(crate) fn finish(&self) {
+        if self.progress.style.is_some() {
+            let counters = &self.counters;
+            let elapsed = counters.started.elapsed();
+            let rows = counters.rows.load(Ordering::Relaxed);
+
+            info!(
+                rows,
+                bytes_read = counters.bytes.read(),
+                elapsed_seconds = elapsed.as_secs_f64(),
+                rows_per_second = rate(rows, elapsed).round() as u64,
+                "Read the XML file"
+            );
+        }
+    }
+}
+
+impl Drop for TableProgress<'_> {
+    fn drop(&mut self) {
+        clear_bar();
+
+        let mut state = self.progress.lock();
+        state
+            .tables
+            .retain(|table| !Arc::ptr_eq(table, &self.counters));
+        state.finished.push(Arc::clone(&self.counters));
+    }
+}
+
+impl Counters {
+    /// Returns the fraction of the file read and the estimated time left,
+    /// if the size of the file is known.
+    fn estimate(&self, read: u64, elapsed: Duration) -> Option<(f64, Option<Duration>)> {
+        let total = self.bytes.total().filter(|total| *total > 0)?;
+        let fraction = (read as f64 / total as f64).min(1.0);
+
+        let eta = (read > 0).then(|| {
+            let left = total.saturating_sub(read) as f64;
+            Duration::from_secs_f64(left * elapsed.as_secs_f64() / read as f64)
+        });
+
+        Some((fraction, eta))
+    }
+
+    /// Logs the progress of the table.
+    ///
+    /// The message is logged outside of the span of the job reporting it,
+    /// which may be loading another table.
+    fn log(&self) {
+        let elapsed = self.started.elapsed();
+        let read = self.bytes.read();
+        let rows = self.rows.load(Ordering::Relaxed);
+        let estimate = self.estimate(read, elapsed);
+
+        info!(
+            parent: None,
+            table = %self.table,
+            percent = estimate.map(|(fraction, _)| (fraction * 1000.0).round() / 10.0),
+            bytes_read = read,
+            file_size = self.bytes.total(),
+            rows,
+            rows_per_second = rate(rows, elapsed).round() as u64,
+            bytes_per_second = rate(read, elapsed).round() as u64,
+            eta_seconds = estimate.and_then(|(_, eta)| eta).map(|eta| eta.as_secs()),
+            "Progress"
+        );
+    }
+
+    /// Draws the progress of the table as a bar, when it is the only table
+    /// being loaded.
+    fn bar(&self) -> String {
+        let elapsed = self.started.elapsed();
+        let read = self.bytes.read();
+        let rows = self.rows.load(Ordering::Relaxed);
+
+        match self.estimate(read, elapsed) {
+            Some((fraction, eta)) => {
+                let filled = (fraction * BAR_WIDTH as f64) as usize;
+
+                format!(
+                    "{} [{}{}] {:.1}% {}/{} {} rows {:.0} rows/s ETA {}",
+                    self.table,
+                    "#".repeat(filled),
+                    " ".repeat(BAR_WIDTH - filled),
+                    fraction * 100.0,
+                    format_bytes(read),
+                    format_bytes(self.bytes.total().unwrap_or_default()),
+                    rows,
+                    rate(rows, elapsed),
+                    eta.map_or_else(|| "-".to_string(), format_duration)
+                )
+            }
+            None => format!(
+                "{} {} {} rows {:.0} rows/s",
+                self.table,
+                format_bytes(read),
+                rows,
+                rate(rows, elapsed)
+            ),
+        }
+    }
+
+    /// Describes the progress of the table in a few words, for a bar
+    /// showing several tables.
+    fn summary(&self) -> String {
+        let elapsed = self.started.elapsed();
+        let read = self.bytes.read();
+
+        match self.estimate(read, elapsed) {
+            Some((fraction, Some(eta))) => format!(
+                "{} {:.0}% ETA {}",
+                self.table,
+                fraction * 100.0,
+                format_duration(eta)
+            ),
+            _ => format!("{} {} rows", self.table, self.rows.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Returns the number of units per second over a duration.
+fn rate(count: u64, elapsed: Duration) -> f64 {
+    match elapsed.as_secs_f64() {
+        0.0 => 0.0,
+        seconds => count as f64 / seconds,
+    }
+}
+
+/// Formats a number of bytes with a binary unit, e.g. `1.5 GiB`.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
+
+/// Formats a duration to the second, e.g. `1h 02m` or `3m 20s`.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+
+    match seconds {
+        0..60 => format!("{}s", seconds),
+        60..3600 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
@@ -0,0 +1,306 @@
+use diesel::{
+    prelude::*,
+    sql_query,
+    sql_types::{BigInt, Bool, Text},
+};
+
+/// A column referencing the `id` of another table, which the schema does
+/// not enforce because the dumps have orphans, like votes on deleted posts.
+///
+/// # Fields
+///
+/// * `table` - The table holding the reference
+/// * `column` - The column holding the `id` of the referenced row
+/// * `referenced_table` - The table the `id` belongs to
+/// * `nullable` - Whether the column can be `NULL`
+#[derive(Debug)]
+pub(crate) struct Reference {
+    pub table: &'static str,
+    pub column: &'static str,
+    pub referenced_table: &'static str,
+    pub nullable: bool,
+}
+
+/// The references between the tables, in load order of their table.
+pub(crate) const REFERENCES: [Reference; 14] = [
+    Reference::new("badges", "user_id", "users", false),
+    Reference::new("comments", "post_id", "posts", false),
+    Reference::new("comments", "user_id", "users", true),
+    Reference::new("posts", "accepted_answer_id", "posts", true),
+    Reference::new("posts", "owner_user_id", "users", true),
+    Reference::new("posts", "last_editor_user_id", "users", true),
+    Reference::new("post_historys", "post_id", "posts", false),
+    Reference::new("post_historys", "user_id", "users", true),
+    Reference::new("post_links", "post_id", "posts", false),
+    Reference::new("post_links", "related_post_id", "posts", false),
+    Reference::new("tags", "excerpt_post_id", "posts", true),
+    Reference::new("tags", "wiki_post_id", "posts", true),
+    Reference::new("votes", "post_id", "posts", false),
+    Reference::new("votes", "user_id", "users", true),
+];
+
+#[derive(Debug, QueryableByName)]
+/// The number of rows matching a query.
+struct Count {
+    #[diesel(sql_type = BigInt)]
+    count: i64,
+}
+
+#[derive(Debug, QueryableByName)]
+/// The state of a foreign key constraint.
+struct ForeignKey {
+    #[diesel(sql_type = Bool)]
+    validated: bool,
+}
+
+impl Reference {
+    /// Describes a reference.
+    const fn new(
+        table: &'static str,
+        column: &'static str,
+        referenced_table: &'static str,
+        nullable: bool,
+    ) -> Self {
+        Self {
+            table,
+            column,
+            referenced_table,
+            nullable,
+        }
+    }
+
+    /// Returns the name of the foreign key constraint enforcing the
+    /// reference, e.g. `fk_comments_post_id`.
+    pub(crate) fn constraint_name(&self) -> String {
+        format!("fk_{}_{}", self.table, self.column)
+    }
+
+    /// Returns the condition matching the rows of `orphan` whose reference
+    /// has no row in the referenced table.
+    fn orphan_condition(&self) -> String {
+        format!(
+            "orphan.{0} IS NOT NULL AND NOT EXISTS \
+             (SELECT 1 FROM {1} WHERE {1}.id = orphan.{0})",
+            self.column, self.referenced_table
+        )
+    }
+}
+
+impl std::fmt::Display for Reference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}.{} -> {}.id",
+            self.table, self.column, self.referenced_table
+        )
+    }
+}
+
+/// Counts the rows whose reference has no row in the referenced table.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `reference` - The reference to check
+///
+/// # Returns
+///
+/// * `Ok(i64)` - The number of orphan rows
+/// * `Err(diesel::result::Error)` - If the query fails
+pub(crate) fn count_orphans(
+    connection: &mut PgConnection,
+    reference: &Reference,
+) -> QueryResult<i64> {
+    // Table and column names cannot be bound, but only those of `REFERENCES`
+    // are ever passed in.
+    let count = sql_query(format!(
+        "SELECT count(*) AS count FROM {} AS orphan WHERE {}",
+        reference.table,
+        reference.orphan_condition()
+    ))
+    .get_result::<Count>(connection)?;
+
+    Ok(count.count)
+}
+
+/// Sets the references without a row in the referenced table to `NULL`.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `reference` - The reference to clear, which must be nullable
+///
+/// # Returns
+///
+/// * `Ok(usize)` - The number of rows updated
+/// * `Err(diesel::result::Error)` - If the update fails
+pub(crate) fn nullify_orphans(
+    connection: &mut PgConnection,
+    reference: &Reference,
+) -> QueryResult<usize> {
+    sql_query(format!(
+        "UPDATE {0} AS orphan SET {1} = NULL WHERE {2}",
+        reference.table,
+        reference.column,
+        reference.orphan_condition()
+    ))
+    .execute(connection)
+}
+
+/// Deletes the rows whose reference has no row in the referenced table.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `reference` - The reference to check
+///
+/// # Returns
+///
+/// * `Ok(usize)` - The number of rows deleted
+/// * `Err(diesel::result::Error)` - If the delete fails
+pub(crate) fn delete_orphans(
+    connection: &mut PgConnection,
+    reference: &Reference,
+) -> QueryResult<usize> {
+    sql_query(format!(
+        "DELETE FROM {} AS orphan WHERE {}",
+        reference.table,
+        reference.orphan_condition()
+    ))
+    .execute(connection)
+}
+
+/// Reads the state of the foreign key constraint of a reference.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `reference` - The reference whose constraint is read
+///
+/// # Returns
+///
+/// * `Ok(Option<bool>)` - Whether the constraint was validated, or `None` if there is none
+/// * `Err(diesel::result::Error)` - If the query fails
+pub(crate) fn foreign_key(
+    connection: &mut PgConnection,
+    reference: &Reference,
+) -> QueryResult<Option<bool>> {
+    let foreign_key = sql_query(
+        "SELECT convalidated AS validated FROM pg_constraint \
+         WHERE conrelid = $1::regclass AND conname = $2 AND contype = 'f'",
+    )
+    .bind::<Text, _>(reference.table)
+    .bind::<Text, _>(reference.constraint_name())
+    .get_result::<ForeignKey>(connection)
+    .optional()?;
+
+    Ok(foreign_key.map(|foreign_key| foreign_key.validated))
+}
+
+/// Adds the foreign key constraint of a reference as `NOT VALID`, so the
+/// existing rows are not checked but new ones are.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `reference` - The reference to enforce, which must not have a constraint yet
+///
+/// # Returns
+///
+/// * `Ok(())` - If the constraint was added
+/// * `Err(diesel::result::Error)` - If the statement fails
+pub(crate) fn add_foreign_key(
+    connection: &mut PgConnection,
+    reference: &Reference,
+) -> QueryResult<()> {
+    sql_query(format!(
+        "ALTER TABLE {} ADD CONSTRAINT {} FOREIGN KEY ({}) REFERENCES {} (id) NOT VALID",
+        reference.table,
+        reference.constraint_name(),
+        reference.column,
+        reference.referenced_table
+    ))
+    .execute(connection)?;
+
+    Ok(())
+}
+
+/// Drops the foreign key constraint of a reference, if it has one.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `reference` - The reference to stop enforcing
+///
+/// # Returns
+///
+/// * `Ok(())` - If the constraint was dropped or did not exist
+/// * `Err(diesel::result::Error)` - If the statement fails
+pub(crate) fn drop_foreign_key(
+    connection: &mut PgConnection,
+    reference: &Reference,
+) -> QueryResult<()> {
+    sql_query(format!(
+        "ALTER TABLE {} DROP CONSTRAINT IF EXISTS {}",
+        reference.table,
+        reference.constraint_name()
+    ))
+    .execute(connection)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{REFERENCES, Reference};
+
+    #[test]
+    fn constraint_name_joins_the_table_and_column() {
+        let reference = Reference::new("comments", "post_id", "posts", false);
+
+        assert_eq!(reference.constraint_name(), "fk_comments_post_id");
+    }
+
+    #[test]
+    fn constraint_names_are_unique_and_fit_in_an_identifier() {
+        let mut names: Vec<String> = REFERENCES.iter().map(Reference::constraint_name).collect();
+        names.sort();
+        names.dedup();
+
+        assert_eq!(names.len(), REFERENCES.len());
+        // PostgreSQL truncates longer identifiers to 63 bytes
+        assert!(names.iter().all(|name| name.len() <= 63));
+    }
+
+    #[test]
+    fn orphan_condition_looks_up_the_referenced_table() {
+        let reference = Reference::new("votes", "user_id", "users", true);
+
+        assert_eq!(
+            reference.orphan_condition(),
+            "orphan.user_id IS NOT NULL AND NOT EXISTS \
+             (SELECT 1 FROM users WHERE users.id = orphan.user_id)"
+        );
+    }
+
+    #[test]
+    fn orphan_condition_qualifies_self_references() {
+        let reference = Reference::new("posts", "accepted_answer_id", "posts", true);
+
+        assert_eq!(
+            reference.orphan_condition(),
+            "orphan.accepted_answer_id IS NOT NULL AND NOT EXISTS \
+             (SELECT 1 FROM posts WHERE posts.id = orphan.accepted_answer_id)"
+        );
+    }
+
+    #[test]
+    fn display_shows_the_referenced_id() {
+        let reference = Reference::new("post_links", "related_post_id", "posts", false);
+
+        assert_eq!(
+            reference.to_string(),
+            "post_links.related_post_id -> posts.id"
+        );
+    }
+}
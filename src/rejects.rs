@@ -0,0 +1,384 @@
+use std::{
+    cell::{Cell, RefCell},
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use diesel::result::{DatabaseErrorInformation, DatabaseErrorKind};
+use serde::Serialize;
+use thiserror::Error;
+use tracing::warn;
+
+use crate::{errors::EtlError, model::RowError, progress::TableProgress};
+
+/// The number of rows of a table read before `--max-error-rate` is checked,
+/// so a bad row among the first ones does not fail the table on its own.
+const MIN_ROWS_FOR_RATE: usize = 1000;
+
+/// A rejected row, as written to a line of the rejects file.
+///
+/// # Fields
+///
+/// * `file` - The path of the XML file the row comes from
+/// * `table` - The table the row was to be written to
+/// * `position` - The byte offset of the row in the decompressed XML
+/// * `id` - The `Id` attribute of the row, if it has a valid one
+/// * `attribute` - The attribute at fault, if a single one is
+/// * `value` - The raw value of the attribute at fault
+/// * `error` - What went wrong
+/// * `row` - The `<row/>` element as found in the file, to replay it
+#[derive(Debug, Serialize)]
+pub(crate) struct Reject<'a> {
+    pub file: &'a str,
+    pub table: &'a str,
+    pub position: Option<u64>,
+    pub id: Option<i32>,
+    pub attribute: Option<&'a str>,
+    pub value: Option<&'a str>,
+    pub error: String,
+    pub row: Option<&'a str>,
+}
+
+impl<'a> Reject<'a> {
+    /// Describes a row of an XML file that could not be read or parsed.
+    ///
+    /// # Parameters
+    ///
+    /// * `file` - The path of the XML file
+    /// * `table` - The table the row was to be written to
+    /// * `error` - The error of the row
+    ///
+    /// # Returns
+    ///
+    /// * `Reject` - The rejected row
+    pub(crate) fn parse(file: &'a str, table: &'a str, error: &'a RowError) -> Self {
+        let attribute = error.error.attribute();
+
+        Self {
+            file,
+            table,
+            position: Some(error.position),
+            id: error.id,
+            attribute: attribute.map(|(name, _)| name),
+            value: attribute.map(|(_, value)| value),
+            error: error.error.to_string(),
+            row: error.row.as_deref(),
+        }
+    }
+
+    /// Describes a parsed row that the database refused, e.g. because it
+    /// breaks a constraint.
+    ///
+    /// # Parameters
+    ///
+    /// * `file` - The path of the XML file
+    /// * `table` - The table the row was to be written to
+    /// * `id` - The `Id` of the row
+    /// * `error` - The error returned by the database
+    ///
+    /// # Returns
+    ///
+    /// * `Reject` - The rejected row, naming the column at fault if the database does
+    pub(crate) fn database(
+        file: &'a str,
+        table: &'a str,
+        id: i32,
+        error: &'a dyn DatabaseErrorInformation,
+    ) -> Self {
+        Self {
+            file,
+            table,
+            position: None,
+            id: Some(id),
+            attribute: error.column_name(),
+            value: None,
+            error: error.message().to_string(),
+            row: None,
+        }
+    }
+}
+
+/// The JSON Lines file rejected rows are written to, shared by the tables
+/// loaded concurrently.
+///
+/// Without a path, rejected rows are only logged.
+#[derive(Debug, Default)]
+pub(crate) struct Rejects {
+    writer: Option<Mutex<BufWriter<File>>>,
+}
+
+impl Rejects {
+    /// Creates the rejects file, replacing any previous one unless a load is
+    /// resumed.
+    ///
+    /// # Parameters
+    ///
+    /// * `path` - The path of the rejects file, if any
+    /// * `append` - Whether to keep the rows rejected by the previous load, whose committed rows are not read again
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Rejects)` - The rejects file
+    /// * `Err(io::Error)` - If the file cannot be created
+    pub(crate) fn create(path: Option<&Path>, append: bool) -> io::Result<Self> {
+        let writer = match path {
+            Some(path) if append => Some(OpenOptions::new().create(true).append(true).open(path)?),
+            Some(path) => Some(File::create(path)?),
+            None => None,
+        };
+
+        Ok(Self {
+            writer: writer.map(|file| Mutex::new(BufWriter::new(file))),
+        })
+    }
+
+    /// Appends rejected rows to the file, as lines of JSON.
+    ///
+    /// # Parameters
+    ///
+    /// * `lines` - The rejected rows, one JSON object per line
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the rows were written, or there is no rejects file
+    /// * `Err(io::Error)` - If the rows cannot be written
+    fn write(&self, lines: &[u8]) -> io::Result<()> {
+        let Some(writer) = &self.writer else {
+            return Ok(());
+        };
+
+        // A thread that panicked while holding the lock left whole lines.
+        writer
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .write_all(lines)
+    }
+
+    /// Writes the buffered rows to the file.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the rows were written, or there is no rejects file
+    /// * `Err(io::Error)` - If the rows cannot be written
+    pub(crate) fn flush(&self) -> io::Result<()> {
+        match &self.writer {
+            Some(writer) => writer
+                .lock()
+                .unwrap_or_else(|error| error.into_inner())
+                .flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+/// The rejected rows of a table went over `--max-errors` or
+/// `--max-error-rate`.
+pub enum BudgetExceeded {
+    #[error("{errors} rows rejected, more than the {max_errors} allowed")]
+    Errors { errors: usize, max_errors: usize },
+
+    #[error("{errors} of {rows} rows rejected, more than the {max_error_rate}% allowed")]
+    Rate {
+        errors: usize,
+        rows: usize,
+        max_error_rate: f64,
+    },
+}
+
+/// The rejected rows of a table being loaded, counted against the error
+/// budget of the table.
+///
+/// The counts are shared by the parsing and the writing of the rows. The
+/// rejected rows are kept aside until the chunk they belong to is committed,
+/// so a chunk rolled back and read again does not write them twice.
+pub(crate) struct TableRejects<'a> {
+    file: &'a str,
+    table: &'a str,
+    rejects: &'a Rejects,
+    progress: &'a TableProgress<'a>,
+    max_errors: Option<usize>,
+    max_error_rate: Option<f64>,
+    rows: Cell<usize>,
+    errors: Cell<usize>,
+    pending: RefCell<Vec<u8>>,
+}
+
+impl<'a> TableRejects<'a> {
+    /// Starts counting the rejected rows of a table.
+    ///
+    /// # Parameters
+    ///
+    /// * `file` - The path of the XML file
+    /// * `table` - The table being loaded
+    /// * `rejects` - The file rejected rows are written to
+    /// * `progress` - Where the rejected rows are counted by reason
+    /// * `max_errors` - The number of rejected rows that fails the table, if any
+    /// * `max_error_rate` - The percentage of rejected rows that fails the table, if any
+    ///
+    /// # Returns
+    ///
+    /// * `TableRejects` - The counter, with no rows yet
+    pub(crate) fn new(
+        file: &'a str,
+        table: &'a str,
+        rejects: &'a Rejects,
+        progress: &'a TableProgress<'a>,
+        max_errors: Option<usize>,
+        max_error_rate: Option<f64>,
+    ) -> Self {
+        Self {
+            file,
+            table,
+            rejects,
+            progress,
+            max_errors,
+            max_error_rate,
+            rows: Cell::new(0),
+            errors: Cell::new(0),
+            pending: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Counts a row read from the XML file, whether it could be parsed or not.
+    pub(crate) fn read(&self) {
+        self.rows.set(self.rows.get() + 1);
+    }
+
+    /// Writes the rows rejected since the last commit to the rejects file,
+    /// once the chunk they belong to is committed.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the rows were written, or there is no rejects file
+    /// * `Err(io::Error)` - If the rows cannot be written
+    pub(crate) fn commit(&self) -> io::Result<()> {
+        let pending = self.pending.take();
+
+        match pending.is_empty() {
+            true => Ok(()),
+            false => self.rejects.write(&pending),
+        }
+    }
+
+    /// Logs and keeps aside a row that could not be parsed, and counts it against
+    /// the budget.
+    ///
+    /// # Parameters
+    ///
+    /// * `error` - The error of the row
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the table can go on
+    /// * `Err(EtlError)` - If the row cannot be written or the budget is exceeded
+    pub(crate) fn parse_error(&self, error: &RowError) -> Result<(), EtlError> {
+        warn!(
+            position = error.position,
+            id = error.id,
+            attribute = error.error.attribute().map(|(name, _)| name),
+            error = %error.error,
+            "Rejected a row that cannot be parsed"
+        );
+
+        self.progress.rejected(error.error.reason());
+        self.keep(&Reject::parse(self.file, self.table, error))?;
+        self.reject()
+    }
+
+    /// Logs and keeps aside a row refused by the database, and counts it against
+    /// the budget.
+    ///
+    /// # Parameters
+    ///
+    /// * `id` - The `Id` of the row
+    /// * `kind` - The kind of constraint the row breaks
+    /// * `error` - The error returned by the database
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the table can go on
+    /// * `Err(EtlError)` - If the row cannot be written or the budget is exceeded
+    pub(crate) fn database_error(
+        &self,
+        id: i32,
+        kind: DatabaseErrorKind,
+        error: &dyn DatabaseErrorInformation,
+    ) -> Result<(), EtlError> {
+        warn!(
+            id,
+            column = error.column_name(),
+            error = error.message(),
+            "Rejected a row refused by the database"
+        );
+
+        self.progress.rejected(&format!("{:?}", kind));
+        self.keep(&Reject::database(self.file, self.table, id, error))?;
+        self.reject()
+    }
+
+    /// Checks the rate of rejected rows once the whole file is read, however
+    /// few rows it has.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the rate is within the budget
+    /// * `Err(EtlError)` - If it is not
+    pub(crate) fn finish(&self) -> Result<(), EtlError> {
+        Ok(self.check_rate()?)
+    }
+
+    /// Keeps a rejected row aside until the next commit, as a line of JSON.
+    fn keep(&self, reject: &Reject) -> io::Result<()> {
+        if self.rejects.writer.is_none() {
+            return Ok(());
+        }
+
+        let mut pending = self.pending.borrow_mut();
+        serde_json::to_writer(&mut *pending, reject)?;
+        pending.push(b'\n');
+
+        Ok(())
+    }
+
+    /// Counts a rejected row, and checks the budget.
+    fn reject(&self) -> Result<(), EtlError> {
+        self.errors.set(self.errors.get() + 1);
+
+        if let Some(max_errors) = self.max_errors
+            && self.errors.get() > max_errors
+        {
+            return Err(BudgetExceeded::Errors {
+                errors: self.errors.get(),
+                max_errors,
+            }
+            .into());
+        }
+
+        if self.rows.get() >= MIN_ROWS_FOR_RATE {
+            self.check_rate()?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks the rate of rejected rows among the rows read so far.
+    fn check_rate(&self) -> Result<(), BudgetExceeded> {
+        let (rows, errors) = (self.rows.get(), self.errors.get());
+
+        match self.max_error_rate {
+            Some(max_error_rate)
+                if rows > 0 && errors as f64 * 100.0 > max_error_rate * rows as f64 =>
+            {
+                Err(BudgetExceeded::Rate {
+                    errors,
+                    rows,
+                    max_error_rate,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::BTreeSet;
+
+use diesel::{pg::CopyFormat, prelude::*, sql_query};
+
+use crate::errors::EtlError;
+
+// The temporary table the ids seen in an XML file are copied to.
+diesel::table! {
+    pg_temp.seen_ids (id) {
+        id -> Int4,
+    }
+}
+
+/// The set of row ids seen in an XML file.
+///
+/// Stack Exchange ids are dense positive integers, so they are kept in a
+/// bitmap, which takes about 12 MB for the 100 million posts of Stack
+/// Overflow. The few negative ids, like the `-1` of the Community user, are
+/// kept aside.
+#[derive(Debug, Default)]
+pub(crate) struct IdSet {
+    bits: Vec<u64>,
+    negative: BTreeSet<i32>,
+}
+
+impl IdSet {
+    /// Adds an id to the set, and returns whether it was not in it yet.
+    pub(crate) fn insert(&mut self, id: i32) -> bool {
+        if id < 0 {
+            return self.negative.insert(id);
+        }
+
+        let (word, bit) = (id as usize / 64, id as usize % 64);
+        if word >= self.bits.len() {
+            self.bits.resize(word + 1, 0);
+        }
+
+        let inserted = self.bits[word] & (1 << bit) == 0;
+        self.bits[word] |= 1 << bit;
+        inserted
+    }
+
+    /// Returns the ids of the set, in ascending order.
+    fn iter(&self) -> impl Iterator<Item = i32> + '_ {
+        let positive = self.bits.iter().enumerate().flat_map(|(word, bits)| {
+            (0..64)
+                .filter(move |bit| bits & (1 << bit) != 0)
+                .map(move |bit| (word * 64 + bit) as i32)
+        });
+
+        self.negative.iter().copied().chain(positive)
+    }
+}
+
+/// Copies the ids seen in an XML file to a temporary table, for the current
+/// transaction.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection, inside a transaction
+/// * `seen` - The ids seen in the XML file
+///
+/// # Returns
+///
+/// * `Ok(())` - If the ids were copied
+/// * `Err(EtlError)` - If a statement fails
+pub(crate) fn copy_seen_ids(connection: &mut PgConnection, seen: &IdSet) -> Result<(), EtlError> {
+    sql_query("CREATE TEMPORARY TABLE seen_ids (id INTEGER PRIMARY KEY) ON COMMIT DROP")
+        .execute(connection)?;
+
+    diesel::copy_from(seen_ids::table)
+        .from_raw_data(seen_ids::table, |out| -> Result<(), EtlError> {
+            for id in seen.iter() {
+                writeln!(out, "{}", id)?;
+            }
+            Ok(())
+        })
+        .with_format(CopyFormat::Text)
+        .execute(connection)?;
+
+    Ok(())
+}
+
+/// Counts the rows of a table that are not in the XML file, including the
+/// rows already marked as removed.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection, inside the transaction of `copy_seen_ids`
+/// * `table_name` - The table to look for removed rows in
+///
+/// # Returns
+///
+/// * `Ok(i64)` - The number of removed rows
+/// * `Err(diesel::result::Error)` - If the query fails
+pub(crate) fn count_removed(connection: &mut PgConnection, table_name: &str) -> QueryResult<i64> {
+    #[derive(QueryableByName)]
+    struct Count {
+        #[diesel(sql_type = diesel::sql_types::BigInt)]
+        removed: i64,
+    }
+
+    sql_query(format!(
+        "SELECT count(*) AS removed FROM {} \
+         WHERE NOT EXISTS (SELECT 1 FROM seen_ids WHERE seen_ids.id = {0}.id)",
+        table_name
+    ))
+    .get_result::<Count>(connection)
+    .map(|count| count.removed)
+}
+
+/// Deletes the rows of a table that are not in the XML file.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection, inside the transaction of `copy_seen_ids`
+/// * `table_name` - The table to delete removed rows from
+///
+/// # Returns
+///
+/// * `Ok(usize)` - The number of deleted rows
+/// * `Err(diesel::result::Error)` - If the statement fails
+pub(crate) fn delete_removed(
+    connection: &mut PgConnection,
+    table_name: &str,
+) -> QueryResult<usize> {
+    sql_query(format!(
+        "DELETE FROM {} WHERE NOT EXISTS (SELECT 1 FROM seen_ids WHERE seen_ids.id = {0}.id)",
+        table_name
+    ))
+    .execute(connection)
+}
+
+/// Sets the `removed_at` column of the rows of a table that are not in the
+/// XML file, keeping the rows themselves.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection, inside the transaction of `copy_seen_ids`
+/// * `table_name` - The table to mark removed rows in
+///
+/// # Returns
+///
+/// * `Ok(usize)` - The number of newly marked rows
+/// * `Err(diesel::result::Error)` - If the statement fails
+pub(crate) fn mark_removed(connection: &mut PgConnection, table_name: &str) -> QueryResult<usize> {
+    sql_query(format!(
+        "UPDATE {} SET removed_at = CURRENT_TIMESTAMP WHERE removed_at IS NULL \
+         AND NOT EXISTS (SELECT 1 FROM seen_ids WHERE seen_ids.id = {0}.id)",
+        table_name
+    ))
+    .execute(connection)
+}
+
+/// Copies the rows of a table that are not in the XML file to another table
+/// with the same columns, like the staging copy of a live table.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection, inside the transaction of `copy_seen_ids`
+/// * `from_table` - The table to copy removed rows from
+/// * `to_table` - The table to copy them to
+///
+/// # Returns
+///
+/// * `Ok(usize)` - The number of copied rows
+/// * `Err(diesel::result::Error)` - If the statement fails
+pub(crate) fn copy_removed(
+    connection: &mut PgConnection,
+    from_table: &str,
+    to_table: &str,
+) -> QueryResult<usize> {
+    sql_query(format!(
+        "INSERT INTO {1} SELECT * FROM {0} \
+         WHERE NOT EXISTS (SELECT 1 FROM seen_ids WHERE seen_ids.id = {0}.id)",
+        from_table, to_table
+    ))
+    .execute(connection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IdSet;
+
+    fn ids(set: &IdSet) -> Vec<i32> {
+        set.iter().collect()
+    }
+
+    #[test]
+    fn empty_set_has_no_ids() {
+        assert!(ids(&IdSet::default()).is_empty());
+    }
+
+    #[test]
+    fn iter_returns_the_ids_in_ascending_order() {
+        let mut set = IdSet::default();
+
+        for id in [1000, 64, 0, 63, 65, 1] {
+            set.insert(id);
+        }
+
+        assert_eq!(ids(&set), [0, 1, 63, 64, 65, 1000]);
+    }
+
+    #[test]
+    fn insert_ignores_duplicates() {
+        let mut set = IdSet::default();
+
+        let inserted: Vec<bool> = [7, 7, -1, -1, 128, 7]
+            .into_iter()
+            .map(|id| set.insert(id))
+            .collect();
+
+        assert_eq!(inserted, [true, false, true, false, true, false]);
+        assert_eq!(ids(&set), [-1, 7, 128]);
+    }
+
+    #[test]
+    fn negative_ids_come_first() {
+        let mut set = IdSet::default();
+
+        for id in [3, -1, i32::MIN, 0, -64] {
+            set.insert(id);
+        }
+
+        assert_eq!(ids(&set), [i32::MIN, -64, -1, 0, 3]);
+    }
+}
@@ -0,0 +1,187 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::{errors::EtlError, model::TABLE_NAMES, progress::Progress, source::STDIN_PATH};
+
+/// How the load of a table ended.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Status {
+    /// The whole XML file was loaded.
+    Loaded,
+
+    /// The table was already loaded by a previous, resumed load.
+    Skipped,
+
+    /// The load of the table failed.
+    Failed,
+}
+
+/// The summary of the load of a table, as written to the report.
+///
+/// # Fields
+///
+/// * `table` - The table
+/// * `file` - The path of the XML file
+/// * `file_size` - The size of the file in bytes, unless it is a pipe that was not read to the end
+/// * `checksum` - The SHA-256 checksum of the file, if it was read to the end
+/// * `status` - How the load ended
+/// * `error` - Why the load failed
+/// * `rows_read` - The number of rows read from the XML file
+/// * `rows_inserted` - The number of rows written to the table
+/// * `rows_rejected` - The number of rows rejected
+/// * `rejected_by_reason` - The number of rows rejected, by `XmlError` variant or database error kind
+/// * `elapsed_seconds` - How long the table took, including its indexes
+/// * `rows_per_second` - The number of rows read per second
+/// * `bytes_per_second` - The number of bytes of the file read per second
+#[derive(Debug, Serialize)]
+pub(crate) struct TableReport {
+    pub table: String,
+    pub file: String,
+    pub file_size: Option<u64>,
+    pub checksum: Option<String>,
+    pub status: Status,
+    pub error: Option<String>,
+    pub rows_read: u64,
+    pub rows_inserted: u64,
+    pub rows_rejected: u64,
+    pub rejected_by_reason: BTreeMap<String, u64>,
+    pub elapsed_seconds: f64,
+    pub rows_per_second: f64,
+    pub bytes_per_second: f64,
+}
+
+/// The report of a load, as written to the file.
+#[derive(Serialize)]
+struct LoadReport<'a> {
+    elapsed_seconds: f64,
+    tables: &'a [TableReport],
+}
+
+/// The summaries of the tables of a load, shared by the jobs.
+pub(crate) struct Report {
+    started: Instant,
+    tables: Mutex<Vec<TableReport>>,
+}
+
+impl Report {
+    /// Starts the report of a load, with no tables yet.
+    ///
+    /// # Returns
+    ///
+    /// * `Report` - The report of the load
+    pub(crate) fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            tables: Mutex::default(),
+        }
+    }
+
+    /// Adds the summary of a table once it is loaded or failed, from the
+    /// counts kept by the progress.
+    ///
+    /// # Parameters
+    ///
+    /// * `table` - The table
+    /// * `file` - The path of the XML file
+    /// * `elapsed` - How long the table took
+    /// * `error` - Why the load failed, if it did
+    /// * `progress` - The progress of the load, holding the counts of the table
+    pub(crate) fn add(
+        &self,
+        table: &str,
+        file: &str,
+        elapsed: Duration,
+        error: Option<&EtlError>,
+        progress: &Progress,
+    ) {
+        let counts = progress.counts(table);
+
+        let status = match (error, &counts) {
+            (Some(_), _) => Status::Failed,
+            (None, None) => Status::Skipped,
+            (None, Some(_)) => Status::Loaded,
+        };
+
+        let (bytes_read, checksum) = counts.as_ref().map_or((0, None), |counts| {
+            (
+                counts.bytes.read(),
+                counts.bytes.checksum().map(str::to_string),
+            )
+        });
+
+        // Standard input has no size, but a pipe read to the end had as many
+        // bytes as were read.
+        let file_size = match file {
+            STDIN_PATH => checksum.as_ref().map(|_| bytes_read),
+            file => fs::metadata(file).ok().map(|metadata| metadata.len()),
+        };
+
+        let (rows_read, rows_inserted, rejected_by_reason) = counts
+            .map(|counts| (counts.rows, counts.written, counts.rejected))
+            .unwrap_or_default();
+
+        let seconds = elapsed.as_secs_f64();
+        let per_second = |count: u64| match seconds {
+            0.0 => 0.0,
+            seconds => count as f64 / seconds,
+        };
+
+        let table_report = TableReport {
+            table: table.to_string(),
+            file: file.to_string(),
+            file_size,
+            checksum,
+            status,
+            error: error.map(ToString::to_string),
+            rows_read,
+            rows_inserted,
+            rows_rejected: rejected_by_reason.values().sum(),
+            rejected_by_reason,
+            elapsed_seconds: seconds,
+            rows_per_second: per_second(rows_read),
+            bytes_per_second: per_second(bytes_read),
+        };
+
+        self.tables
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .push(table_report);
+    }
+
+    /// Writes the report as JSON, with the tables in load order.
+    ///
+    /// # Parameters
+    ///
+    /// * `path` - The path of the report, which is replaced
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the report was written
+    /// * `Err(io::Error)` - If the file cannot be written
+    pub(crate) fn write(&self, path: &Path) -> io::Result<()> {
+        let mut tables = self
+            .tables
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        tables.sort_by_key(|table| TABLE_NAMES.iter().position(|name| *name == table.table));
+
+        let report = LoadReport {
+            elapsed_seconds: self.started.elapsed().as_secs_f64(),
+            tables: &tables,
+        };
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, &report)?;
+        writer.write_all(b"\n")?;
+        writer.flush()
+    }
+}
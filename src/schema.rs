@@ -8,6 +8,7 @@ diesel::table! {
         date -> Timestamp,
         class -> Int2,
         tag_based -> Bool,
+        removed_at -> Nullable<Timestamp>,
     }
 }
 
@@ -22,6 +23,26 @@ diesel::table! {
         user_display_name -> Nullable<Varchar>,
         user_id -> Nullable<Int4>,
         content_license -> Nullable<Text>,
+        removed_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    load_checkpoints (table_name) {
+        table_name -> Text,
+        file_path -> Text,
+        last_id -> Nullable<Int4>,
+        completed -> Bool,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    load_dropped_indexes (index_name) {
+        index_name -> Text,
+        table_name -> Text,
+        definition -> Text,
+        dropped_at -> Timestamp,
     }
 }
 
@@ -37,6 +58,7 @@ diesel::table! {
         comment -> Nullable<Text>,
         text -> Nullable<Text>,
         content_license -> Nullable<Text>,
+        removed_at -> Nullable<Timestamp>,
     }
 }
 
@@ -47,6 +69,7 @@ diesel::table! {
         post_id -> Int4,
         related_post_id -> Int4,
         link_type_id -> Int2,
+        removed_at -> Nullable<Timestamp>,
     }
 }
 
@@ -74,6 +97,7 @@ diesel::table! {
         close_date -> Nullable<Timestamp>,
         community_owned_date -> Nullable<Timestamp>,
         content_license -> Nullable<Text>,
+        removed_at -> Nullable<Timestamp>,
     }
 }
 
@@ -86,6 +110,7 @@ diesel::table! {
         wiki_post_id -> Nullable<Int4>,
         is_moderator_only -> Int2,
         is_required -> Int2,
+        removed_at -> Nullable<Timestamp>,
     }
 }
 
@@ -105,6 +130,7 @@ diesel::table! {
         profile_image_url -> Nullable<Text>,
         email_hash -> Nullable<Text>,
         account_id -> Nullable<Int4>,
+        removed_at -> Nullable<Timestamp>,
     }
 }
 
@@ -116,12 +142,15 @@ diesel::table! {
         user_id -> Nullable<Int4>,
         creation_date -> Timestamp,
         bounty_amount -> Nullable<Int4>,
+        removed_at -> Nullable<Timestamp>,
     }
 }
 
 diesel::allow_tables_to_appear_in_same_query!(
     badges,
     comments,
+    load_checkpoints,
+    load_dropped_indexes,
     post_historys,
     post_links,
     posts,
@@ -0,0 +1,393 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread::JoinHandle,
+};
+
+use flate2::bufread::MultiGzDecoder;
+use sevenz_rust2::{ArchiveReader, Password};
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+/// A buffered source of XML, possibly decompressed on the fly.
+pub(crate) type XmlSource = Box<dyn BufRead + Send>;
+
+/// The compression formats recognized in XML inputs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+    SevenZip,
+}
+
+impl Compression {
+    /// Detects the compression of an input from its first bytes, falling
+    /// back to the extension of its path.
+    fn detect(magic: &[u8], path: &Path) -> Self {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else if magic.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else if magic.starts_with(&[0x37, 0x7a, 0xbc, 0xaf, 0x27, 0x1c]) {
+            Compression::SevenZip
+        } else {
+            let extension = path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .map(str::to_ascii_lowercase);
+
+            match extension.as_deref() {
+                Some("gz") => Compression::Gzip,
+                Some("zst") => Compression::Zstd,
+                Some("bz2") => Compression::Bzip2,
+                Some("7z") => Compression::SevenZip,
+                _ => Compression::None,
+            }
+        }
+    }
+}
+
+/// The path that stands for standard input.
+pub(crate) const STDIN_PATH: &str = "-";
+
+/// How much of an XML input has been read, shared with the thread
+/// reporting the progress of its table.
+///
+/// The bytes are counted before decompression, so they can be compared to
+/// the size of the file. The entries of `.7z` archives are counted once
+/// decompressed instead, against their uncompressed size. The file itself
+/// is hashed when a checksum is wanted, whatever its format.
+#[derive(Debug, Default)]
+pub(crate) struct BytesRead {
+    read: AtomicU64,
+    total: OnceLock<u64>,
+    hash: bool,
+    checksum: OnceLock<String>,
+}
+
+impl BytesRead {
+    /// Creates a counter that also computes the SHA-256 checksum of the
+    /// input.
+    pub(crate) fn with_checksum() -> Self {
+        Self {
+            hash: true,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the number of bytes read so far.
+    pub(crate) fn read(&self) -> u64 {
+        self.read.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of bytes of the input, unless it is a pipe.
+    pub(crate) fn total(&self) -> Option<u64> {
+        self.total.get().copied()
+    }
+
+    /// Returns the hexadecimal SHA-256 checksum of the input, once it has
+    /// been read to the end.
+    pub(crate) fn checksum(&self) -> Option<&str> {
+        self.checksum.get().map(String::as_str)
+    }
+}
+
+/// A reader counting, and possibly hashing, the bytes read through it.
+struct CountingReader<R> {
+    inner: R,
+    hasher: Option<Sha256>,
+    bytes: Arc<BytesRead>,
+}
+
+impl<R> CountingReader<R> {
+    /// Starts counting the bytes read from a reader.
+    fn new(inner: R, bytes: Arc<BytesRead>) -> Self {
+        Self {
+            inner,
+            hasher: bytes.hash.then(Sha256::new),
+            bytes,
+        }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bytes.read.fetch_add(read as u64, Ordering::Relaxed);
+
+        if read > 0 {
+            if let Some(hasher) = &mut self.hasher {
+                hasher.update(&buf[..read]);
+            }
+        } else if !buf.is_empty()
+            && let Some(hasher) = self.hasher.take()
+        {
+            let _ = self.bytes.checksum.set(format!("{:x}", hasher.finalize()));
+        }
+
+        Ok(read)
+    }
+}
+
+/// Opens an XML input, decompressing `.gz`, `.zst`, `.bz2` and `.7z` files
+/// while they are read, so the XML is never written to disk.
+///
+/// # Parameters
+///
+/// * `path` - The path to the XML file or compressed archive, or `-` for standard input
+/// * `file_name` - The name of the XML file to read from multi-file `.7z` archives
+/// * `bytes` - Counts the bytes read from the input
+///
+/// # Returns
+///
+/// * `Ok(XmlSource)` - A buffered reader over the XML
+/// * `Err(io::Error)` - If the file cannot be opened or is not a valid archive
+pub(crate) fn open(path: &Path, file_name: &str, bytes: Arc<BytesRead>) -> io::Result<XmlSource> {
+    let is_stdin = path == Path::new(STDIN_PATH);
+
+    let (input, size): (Box<dyn Read + Send>, _) = if is_stdin {
+        (Box::new(io::stdin()), None)
+    } else {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        (Box::new(file), Some(size))
+    };
+
+    let mut reader = BufReader::new(CountingReader::new(input, Arc::clone(&bytes)));
+    let compression = Compression::detect(reader.fill_buf()?, path);
+
+    debug!(path = %path.display(), ?compression, size, "Opened XML input");
+
+    if let Some(size) = size
+        && compression != Compression::SevenZip
+    {
+        let _ = bytes.total.set(size);
+    }
+
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+        Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?)),
+        Compression::Bzip2 => Box::new(BufReader::new(bzip2::bufread::MultiBzDecoder::new(reader))),
+        // The index of a 7z archive is at its end, which a pipe cannot seek to.
+        Compression::SevenZip if is_stdin => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "7z archives cannot be read from standard input, pipe `7z x -so` instead",
+            ));
+        }
+        Compression::SevenZip => {
+            let reader = SevenZipReader::open(path, file_name, &bytes)?;
+
+            // The archive is read by its own reader, so count the entry as
+            // it is decompressed instead of the bytes peeked above. The
+            // archive is hashed by the reader.
+            bytes.read.store(0, Ordering::Relaxed);
+            let _ = bytes.total.set(reader.size);
+
+            Box::new(BufReader::new(CountingReader {
+                inner: reader,
+                hasher: None,
+                bytes,
+            }))
+        }
+    })
+}
+
+/// Streams a single file out of a `.7z` archive.
+///
+/// The archive format only offers a callback-based API, so the entry is
+/// decompressed on a background thread and handed over through a pipe.
+/// When a checksum is wanted, the whole archive is hashed by another thread,
+/// since the entry may only be a part of it.
+struct SevenZipReader {
+    pipe: io::PipeReader,
+    size: u64,
+    decoder: Option<JoinHandle<io::Result<()>>>,
+    hasher: Option<(JoinHandle<io::Result<String>>, Arc<BytesRead>)>,
+}
+
+impl SevenZipReader {
+    /// Starts decompressing the entry named `file_name`, or the only entry
+    /// of a single-file archive such as `stackoverflow.com-Posts.7z`, and
+    /// hashing the archive if `bytes` wants a checksum.
+    fn open(path: &Path, file_name: &str, bytes: &Arc<BytesRead>) -> io::Result<Self> {
+        let mut archive = ArchiveReader::open(path, Password::empty()).map_err(io::Error::other)?;
+
+        let entries: Vec<(&str, u64)> = archive
+            .archive()
+            .files
+            .iter()
+            .filter(|entry| entry.has_stream && !entry.is_directory)
+            .map(|entry| (entry.name.as_str(), entry.size))
+            .collect();
+
+        let (entry_name, size) = entries
+            .iter()
+            .find(|(name, _)| {
+                let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+                name.eq_ignore_ascii_case(file_name)
+            })
+            .or(match entries.as_slice() {
+                [entry] => Some(entry),
+                _ => None,
+            })
+            .map(|(name, size)| (name.to_string(), *size))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} not found in {}", file_name, path.display()),
+                )
+            })?;
+
+        let (pipe, mut writer) = io::pipe()?;
+
+        let decoder = std::thread::spawn(move || {
+            let mut found = false;
+
+            archive
+                .for_each_entries(|entry, data| {
+                    if found {
+                        // Stop decoding once the entry has been streamed.
+                        return Ok(false);
+                    }
+
+                    if entry.name == entry_name {
+                        found = true;
+                        io::copy(data, &mut writer)?;
+                    } else {
+                        // Entries of solid archives share one stream, so
+                        // the ones before the entry must still be decoded.
+                        io::copy(data, &mut io::sink())?;
+                    }
+
+                    Ok(true)
+                })
+                .map_err(io::Error::other)
+        });
+
+        let hasher = match bytes.hash {
+            true => {
+                let mut file = File::open(path)?;
+                let hasher = std::thread::spawn(move || {
+                    let mut hasher = Sha256::new();
+                    io::copy(&mut file, &mut hasher)?;
+                    Ok(format!("{:x}", hasher.finalize()))
+                });
+
+                Some((hasher, Arc::clone(bytes)))
+            }
+            false => None,
+        };
+
+        Ok(Self {
+            pipe,
+            size,
+            decoder: Some(decoder),
+            hasher,
+        })
+    }
+}
+
+impl Read for SevenZipReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.pipe.read(buf)?;
+
+        // The pipe is closed once decoding ends, so report decoding errors
+        // instead of a silently truncated file.
+        if read == 0
+            && !buf.is_empty()
+            && let Some(decoder) = self.decoder.take()
+        {
+            decoder
+                .join()
+                .map_err(|_| io::Error::other("7z decoder thread panicked"))??;
+
+            // Like other files, the checksum is only known once the entry
+            // was read to the end.
+            if let Some((hasher, bytes)) = self.hasher.take() {
+                let checksum = hasher
+                    .join()
+                    .map_err(|_| io::Error::other("7z hasher thread panicked"))??;
+                let _ = bytes.checksum.set(checksum);
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::Compression;
+
+    #[test]
+    fn detect_reads_the_magic_bytes() {
+        let path = Path::new("Posts.xml");
+
+        assert_eq!(
+            Compression::detect(&[0x1f, 0x8b, 0x08, 0x00], path),
+            Compression::Gzip
+        );
+        assert_eq!(
+            Compression::detect(&[0x28, 0xb5, 0x2f, 0xfd], path),
+            Compression::Zstd
+        );
+        assert_eq!(Compression::detect(b"BZh91AY&SY", path), Compression::Bzip2);
+        assert_eq!(
+            Compression::detect(&[0x37, 0x7a, 0xbc, 0xaf, 0x27, 0x1c], path),
+            Compression::SevenZip
+        );
+        assert_eq!(Compression::detect(b"<?xml vers", path), Compression::None);
+    }
+
+    #[test]
+    fn detect_falls_back_to_the_extension() {
+        let magic = b"\0\0\0\0";
+
+        assert_eq!(
+            Compression::detect(magic, Path::new("Posts.xml.gz")),
+            Compression::Gzip
+        );
+        assert_eq!(
+            Compression::detect(magic, Path::new("Posts.xml.ZST")),
+            Compression::Zstd
+        );
+        assert_eq!(
+            Compression::detect(magic, Path::new("Posts.xml.bz2")),
+            Compression::Bzip2
+        );
+        assert_eq!(
+            Compression::detect(magic, Path::new("site.com-Posts.7z")),
+            Compression::SevenZip
+        );
+        assert_eq!(
+            Compression::detect(magic, Path::new("Posts.xml")),
+            Compression::None
+        );
+        assert_eq!(Compression::detect(b"", Path::new("-")), Compression::None);
+    }
+
+    #[test]
+    fn detect_prefers_the_magic_bytes_over_the_extension() {
+        assert_eq!(
+            Compression::detect(&[0x1f, 0x8b], Path::new("Posts.xml.zst")),
+            Compression::Gzip
+        );
+        assert_eq!(
+            Compression::detect(b"<?xml", Path::new("Posts.xml")),
+            Compression::None
+        );
+    }
+}
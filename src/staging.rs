@@ -0,0 +1,531 @@
+use diesel::{
+    prelude::*,
+    sql_query,
+    sql_types::{Array, Bool, Text},
+};
+
+use crate::indexes::IndexDefinition;
+
+/// The schema holding the staging copies of the tables.
+pub(crate) const STAGING_SCHEMA: &str = "staging";
+
+#[derive(Debug, QueryableByName)]
+/// Represents a view depending on a swapped table, dropped and recreated by
+/// the swap.
+///
+/// # Fields
+///
+/// * `view_name` - The qualified name of the view
+/// * `materialized` - Whether the view is a materialized view
+/// * `definition` - The query of the view, as returned by `pg_get_viewdef`
+/// * `indexes` - The `CREATE INDEX` statements of the view
+struct DependentView {
+    #[diesel(sql_type = Text)]
+    view_name: String,
+    #[diesel(sql_type = Bool)]
+    materialized: bool,
+    #[diesel(sql_type = Text)]
+    definition: String,
+    #[diesel(sql_type = Array<Text>)]
+    indexes: Vec<String>,
+}
+
+#[derive(Debug, QueryableByName)]
+/// Represents a foreign key of or referencing a swapped table, dropped and
+/// recreated as `NOT VALID` by the swap.
+///
+/// # Fields
+///
+/// * `constraint_name` - The name of the constraint
+/// * `table_name` - The qualified name of the table holding the constraint
+/// * `validated` - Whether the constraint was validated before the swap
+/// * `definition` - The `ALTER TABLE` statement recreating the constraint
+pub(crate) struct ForeignKey {
+    #[diesel(sql_type = Text)]
+    pub constraint_name: String,
+    #[diesel(sql_type = Text)]
+    pub table_name: String,
+    #[diesel(sql_type = Bool)]
+    pub validated: bool,
+    #[diesel(sql_type = Text)]
+    definition: String,
+}
+
+/// The views and foreign keys recreated by a swap.
+///
+/// # Fields
+///
+/// * `views` - The names of the recreated views
+/// * `foreign_keys` - The recreated foreign keys, now `NOT VALID`
+#[derive(Debug)]
+pub(crate) struct Swap {
+    pub views: Vec<String>,
+    pub foreign_keys: Vec<ForeignKey>,
+}
+
+#[derive(Debug, QueryableByName)]
+/// Represents a statement restoring the owner or a privilege of a table or
+/// view recreated by the swap.
+struct Privilege {
+    #[diesel(sql_type = Text)]
+    statement: String,
+}
+
+#[derive(Debug, QueryableByName)]
+/// Represents the schema a table lives in.
+struct TableSchema {
+    #[diesel(sql_type = Text)]
+    schema_name: String,
+}
+
+/// Creates the schema holding the staging tables, if it does not exist.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+///
+/// # Returns
+///
+/// * `Ok(())` - If the schema exists
+/// * `Err(diesel::result::Error)` - If the statement fails
+pub(crate) fn create_schema(connection: &mut PgConnection) -> QueryResult<()> {
+    sql_query(format!("CREATE SCHEMA IF NOT EXISTS {}", STAGING_SCHEMA)).execute(connection)?;
+
+    Ok(())
+}
+
+/// Creates an empty `UNLOGGED` staging copy of a table.
+///
+/// The copy has the columns, defaults and check constraints of the table,
+/// but no indexes, so rows are written as fast as possible. A staging table
+/// left over by a failed load is replaced. The staging schema must exist.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `table_name` - The table to create the staging copy of
+///
+/// # Returns
+///
+/// * `Ok(())` - If the staging table was created
+/// * `Err(diesel::result::Error)` - If a statement fails
+pub(crate) fn create_table(connection: &mut PgConnection, table_name: &str) -> QueryResult<()> {
+    sql_query(format!(
+        "DROP TABLE IF EXISTS {}.{}",
+        STAGING_SCHEMA, table_name
+    ))
+    .execute(connection)?;
+    sql_query(format!(
+        "CREATE UNLOGGED TABLE {0}.{1} (LIKE {1} INCLUDING DEFAULTS INCLUDING CONSTRAINTS)",
+        STAGING_SCHEMA, table_name
+    ))
+    .execute(connection)?;
+
+    Ok(())
+}
+
+/// Makes the unqualified table names of the connection resolve to the
+/// staging tables, so the models are written to them.
+///
+/// Tables without a staging copy, like `load_checkpoints`, still resolve to
+/// the live tables.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+///
+/// # Returns
+///
+/// * `Ok(())` - If the search path was changed
+/// * `Err(diesel::result::Error)` - If the statement fails
+pub(crate) fn use_staging(connection: &mut PgConnection) -> QueryResult<()> {
+    sql_query(format!(
+        "SELECT set_config('search_path', '{}, ' || current_setting('search_path'), false)",
+        STAGING_SCHEMA
+    ))
+    .execute(connection)?;
+
+    Ok(())
+}
+
+/// Makes the unqualified table names of the connection resolve to the live
+/// tables again.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+///
+/// # Returns
+///
+/// * `Ok(())` - If the search path was reset
+/// * `Err(diesel::result::Error)` - If the statement fails
+pub(crate) fn use_live(connection: &mut PgConnection) -> QueryResult<()> {
+    sql_query("RESET search_path").execute(connection)?;
+
+    Ok(())
+}
+
+/// Makes a loaded staging table durable, and lists the statements creating
+/// the constraints and indexes of the live table on it.
+///
+/// The constraints and indexes keep the names of the live ones, which is
+/// allowed since they live in another schema.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection, using the live tables
+/// * `table_name` - The table whose staging copy was loaded
+///
+/// # Returns
+///
+/// * `Ok(Vec<IndexDefinition>)` - The constraints and indexes to build, primary key first
+/// * `Err(diesel::result::Error)` - If a statement fails
+pub(crate) fn set_logged(
+    connection: &mut PgConnection,
+    table_name: &str,
+) -> QueryResult<Vec<IndexDefinition>> {
+    sql_query(format!(
+        "ALTER TABLE {}.{} SET LOGGED",
+        STAGING_SCHEMA, table_name
+    ))
+    .execute(connection)?;
+
+    sql_query(
+        "SELECT conname::text AS index_name, \
+         format('ALTER TABLE %I.%I ADD CONSTRAINT %I %s', $2, $1, conname, pg_get_constraintdef(oid)) \
+         AS definition \
+         FROM pg_constraint \
+         WHERE conrelid = to_regclass($1) AND contype IN ('p', 'u', 'x') \
+         UNION ALL \
+         SELECT index_class.relname::text, \
+         format('CREATE %sINDEX %I ON %I.%I%s', \
+         CASE WHEN pg_index.indisunique THEN 'UNIQUE ' ELSE '' END, index_class.relname, $2, $1, \
+         substring(pg_get_indexdef(pg_index.indexrelid) FROM ' USING .*$')) \
+         FROM pg_index \
+         JOIN pg_class AS index_class ON index_class.oid = pg_index.indexrelid \
+         WHERE pg_index.indrelid = to_regclass($1) \
+         AND NOT EXISTS (SELECT 1 FROM pg_constraint WHERE conindid = pg_index.indexrelid)",
+    )
+    .bind::<Text, _>(table_name)
+    .bind::<Text, _>(STAGING_SCHEMA)
+    .load::<IndexDefinition>(connection)
+}
+
+/// Builds a constraint or index listed by `set_logged` on a staging table.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `index` - The constraint or index to build
+///
+/// # Returns
+///
+/// * `Ok(())` - If the constraint or index was built
+/// * `Err(diesel::result::Error)` - If the statement fails
+pub(crate) fn build_index(
+    connection: &mut PgConnection,
+    index: &IndexDefinition,
+) -> QueryResult<()> {
+    sql_query(&index.definition).execute(connection)?;
+
+    Ok(())
+}
+
+/// Lists the statements giving tables or views their current owner and
+/// privileges back once they are recreated under the same name.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `relation_names` - The tables and views to read the owner and privileges of
+///
+/// # Returns
+///
+/// * `Ok(Vec<Privilege>)` - The `ALTER ... OWNER TO` statements, then the `GRANT` statements
+/// * `Err(diesel::result::Error)` - If the query fails
+fn privileges(
+    connection: &mut PgConnection,
+    relation_names: &[String],
+) -> QueryResult<Vec<Privilege>> {
+    sql_query(
+        "SELECT statement FROM ( \
+         SELECT 0 AS step, format('ALTER %s %s OWNER TO %I', \
+         CASE relkind WHEN 'm' THEN 'MATERIALIZED VIEW' WHEN 'v' THEN 'VIEW' ELSE 'TABLE' END, \
+         oid::regclass, pg_get_userbyid(relowner)) AS statement \
+         FROM pg_class \
+         WHERE oid = ANY(SELECT to_regclass(name) FROM unnest($1) AS name) \
+         UNION ALL \
+         SELECT 1, format('GRANT %s ON %s TO %s%s', acl.privilege_type, pg_class.oid::regclass, \
+         CASE acl.grantee WHEN 0 THEN 'PUBLIC' ELSE quote_ident(pg_get_userbyid(acl.grantee)) END, \
+         CASE WHEN acl.is_grantable THEN ' WITH GRANT OPTION' ELSE '' END) \
+         FROM pg_class CROSS JOIN LATERAL aclexplode(pg_class.relacl) AS acl \
+         WHERE pg_class.oid = ANY(SELECT to_regclass(name) FROM unnest($1) AS name) \
+         ) AS privileges ORDER BY step",
+    )
+    .bind::<Array<Text>, _>(relation_names)
+    .load::<Privilege>(connection)
+}
+
+/// Replaces live tables with their loaded staging copies in a single
+/// transaction.
+///
+/// The live tables are dropped and the staging tables moved into their
+/// schema, so readers see either all the old tables or all the new ones.
+/// Views depending on the live tables, like `post_stats`, and the views
+/// built on those views are dropped with them, and recreated with their
+/// indexes on the new tables. The owner and the privileges of the tables
+/// and views are given back to the new ones.
+///
+/// Foreign keys of the live tables, and of other tables referencing them,
+/// like those added by `check-references`, are dropped with them too. They
+/// are recreated as `NOT VALID`, since the new rows were not checked.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection, using the live tables
+/// * `table_names` - The tables to replace with their staging copies
+///
+/// # Returns
+///
+/// * `Ok(Swap)` - The recreated views and foreign keys
+/// * `Err(diesel::result::Error)` - If a statement fails, in which case nothing was swapped
+pub(crate) fn swap_tables(
+    connection: &mut PgConnection,
+    table_names: &[&str],
+) -> QueryResult<Swap> {
+    let table_names: Vec<String> = table_names.iter().map(|name| name.to_string()).collect();
+
+    connection.transaction(|connection| {
+        // Wait for the running queries, and keep new ones out until the
+        // swap is committed.
+        sql_query(format!(
+            "LOCK TABLE {} IN ACCESS EXCLUSIVE MODE",
+            table_names.join(", ")
+        ))
+        .execute(connection)?;
+
+        // Views on those views are dropped too, so the dependencies are
+        // followed down, and every view is recreated after the views it
+        // reads from. The rule of a view also depends on the view itself.
+        let views = sql_query(
+            "WITH RECURSIVE dependents (oid, depth) AS ( \
+             SELECT pg_rewrite.ev_class, 1 \
+             FROM pg_depend \
+             JOIN pg_rewrite ON pg_rewrite.oid = pg_depend.objid \
+             WHERE pg_depend.refobjid = ANY(SELECT to_regclass(name) FROM unnest($1) AS name) \
+             UNION \
+             SELECT pg_rewrite.ev_class, dependents.depth + 1 \
+             FROM dependents \
+             JOIN pg_depend ON pg_depend.refobjid = dependents.oid \
+             JOIN pg_rewrite ON pg_rewrite.oid = pg_depend.objid \
+             WHERE pg_rewrite.ev_class <> dependents.oid) \
+             SELECT view_class.oid::regclass::text AS view_name, \
+             view_class.relkind = 'm' AS materialized, \
+             pg_get_viewdef(view_class.oid) AS definition, \
+             ARRAY(SELECT pg_get_indexdef(indexrelid) FROM pg_index \
+             WHERE indrelid = view_class.oid ORDER BY indexrelid) AS indexes \
+             FROM (SELECT oid, max(depth) AS depth FROM dependents GROUP BY oid) AS dependent \
+             JOIN pg_class AS view_class ON view_class.oid = dependent.oid \
+             WHERE view_class.relkind IN ('m', 'v') \
+             ORDER BY dependent.depth, view_name",
+        )
+        .bind::<Array<Text>, _>(&table_names)
+        .load::<DependentView>(connection)?;
+
+        let foreign_keys = sql_query(
+            "SELECT conname::text AS constraint_name, conrelid::regclass::text AS table_name, \
+             convalidated AS validated, \
+             format('ALTER TABLE %s ADD CONSTRAINT %I %s NOT VALID', conrelid::regclass, conname, \
+             regexp_replace(pg_get_constraintdef(oid), ' NOT VALID$', '')) AS definition \
+             FROM pg_constraint \
+             WHERE contype = 'f' \
+             AND (conrelid = ANY(SELECT to_regclass(name) FROM unnest($1) AS name) \
+             OR confrelid = ANY(SELECT to_regclass(name) FROM unnest($1) AS name)) \
+             ORDER BY table_name, constraint_name",
+        )
+        .bind::<Array<Text>, _>(&table_names)
+        .load::<ForeignKey>(connection)?;
+
+        // Dropping the tables and views loses their privileges, so they are
+        // read first.
+        let relation_names: Vec<String> = table_names
+            .iter()
+            .cloned()
+            .chain(views.iter().map(|view| view.view_name.clone()))
+            .collect();
+        let privileges = privileges(connection, &relation_names)?;
+
+        for table_name in &table_names {
+            // Move the staging table into the schema of the live table.
+            let schema = sql_query(
+                "SELECT relnamespace::regnamespace::text AS schema_name \
+                 FROM pg_class WHERE oid = to_regclass($1)",
+            )
+            .bind::<Text, _>(table_name)
+            .get_result::<TableSchema>(connection)?
+            .schema_name;
+
+            sql_query(format!("DROP TABLE {} CASCADE", table_name)).execute(connection)?;
+            sql_query(format!(
+                "ALTER TABLE {}.{} SET SCHEMA {}",
+                STAGING_SCHEMA, table_name, schema
+            ))
+            .execute(connection)?;
+        }
+
+        for view in &views {
+            let kind = match view.materialized {
+                true => "MATERIALIZED VIEW",
+                false => "VIEW",
+            };
+
+            sql_query(format!(
+                "CREATE {} {} AS {}",
+                kind, view.view_name, view.definition
+            ))
+            .execute(connection)?;
+
+            for index in &view.indexes {
+                sql_query(index).execute(connection)?;
+            }
+        }
+
+        for foreign_key in &foreign_keys {
+            sql_query(&foreign_key.definition).execute(connection)?;
+        }
+
+        for privilege in &privileges {
+            sql_query(&privilege.statement).execute(connection)?;
+        }
+
+        Ok(Swap {
+            views: views.into_iter().map(|view| view.view_name).collect(),
+            foreign_keys,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{prelude::*, sql_query, sql_types::Bool};
+
+    use super::{build_index, create_schema, create_table, set_logged, swap_tables};
+
+    #[derive(QueryableByName)]
+    struct Check {
+        #[diesel(sql_type = Bool)]
+        check: bool,
+    }
+
+    fn check(connection: &mut PgConnection, query: &str) -> bool {
+        sql_query(query)
+            .get_result::<Check>(connection)
+            .unwrap()
+            .check
+    }
+
+    #[test]
+    #[ignore = "needs a PostgreSQL database in STACKXCHG2PGSQL_TEST_DATABASE_URL"]
+    fn swap_keeps_the_owner_and_privileges() {
+        let database_url = std::env::var("STACKXCHG2PGSQL_TEST_DATABASE_URL")
+            .expect("STACKXCHG2PGSQL_TEST_DATABASE_URL is not set");
+        let mut connection = PgConnection::establish(&database_url).unwrap();
+
+        // Roles are rolled back with the rest of the test transaction
+        connection.begin_test_transaction().unwrap();
+
+        for statement in [
+            "CREATE ROLE swap_test_owner",
+            "CREATE ROLE swap_test_reader",
+            "CREATE TABLE swap_test (id integer PRIMARY KEY, score integer)",
+            "CREATE VIEW swap_test_scores AS SELECT id, score FROM swap_test",
+            "CREATE VIEW swap_test_top_scores AS SELECT id FROM swap_test_scores WHERE score > 10",
+            "ALTER TABLE swap_test OWNER TO swap_test_owner",
+            "GRANT SELECT, INSERT ON swap_test TO swap_test_reader",
+            "GRANT SELECT ON swap_test_scores TO swap_test_reader WITH GRANT OPTION",
+            "GRANT SELECT ON swap_test_top_scores TO swap_test_reader",
+        ] {
+            sql_query(statement).execute(&mut connection).unwrap();
+        }
+
+        create_schema(&mut connection).unwrap();
+        create_table(&mut connection, "swap_test").unwrap();
+        let swap = swap_tables(&mut connection, &["swap_test"]).unwrap();
+
+        assert_eq!(swap.views, ["swap_test_scores", "swap_test_top_scores"]);
+        assert!(check(
+            &mut connection,
+            "SELECT relnamespace = 'public'::regnamespace \
+             AND pg_get_userbyid(relowner) = 'swap_test_owner' AS check \
+             FROM pg_class WHERE oid = 'swap_test'::regclass"
+        ));
+        assert!(check(
+            &mut connection,
+            "SELECT has_table_privilege('swap_test_reader', 'swap_test', 'SELECT') \
+             AND has_table_privilege('swap_test_reader', 'swap_test', 'INSERT') \
+             AND NOT has_table_privilege('swap_test_reader', 'swap_test', 'DELETE') AS check"
+        ));
+        assert!(check(
+            &mut connection,
+            "SELECT has_table_privilege('swap_test_reader', 'swap_test_scores', \
+             'SELECT WITH GRANT OPTION') \
+             AND has_table_privilege('swap_test_reader', 'swap_test_top_scores', 'SELECT') AS check"
+        ));
+    }
+
+    #[test]
+    #[ignore = "needs a PostgreSQL database in STACKXCHG2PGSQL_TEST_DATABASE_URL"]
+    fn swap_recreates_the_foreign_keys_not_valid() {
+        let database_url = std::env::var("STACKXCHG2PGSQL_TEST_DATABASE_URL")
+            .expect("STACKXCHG2PGSQL_TEST_DATABASE_URL is not set");
+        let mut connection = PgConnection::establish(&database_url).unwrap();
+        connection.begin_test_transaction().unwrap();
+
+        for statement in [
+            "CREATE TABLE swap_test_users (id integer PRIMARY KEY)",
+            "CREATE TABLE swap_test_posts (id integer PRIMARY KEY, \
+             owner_user_id integer CONSTRAINT fk_swap_test_posts_owner_user_id \
+             REFERENCES swap_test_users (id))",
+            "CREATE TABLE swap_test_votes (id integer PRIMARY KEY, post_id integer)",
+            "ALTER TABLE swap_test_votes ADD CONSTRAINT fk_swap_test_votes_post_id \
+             FOREIGN KEY (post_id) REFERENCES swap_test_posts (id) NOT VALID",
+        ] {
+            sql_query(statement).execute(&mut connection).unwrap();
+        }
+
+        create_schema(&mut connection).unwrap();
+        create_table(&mut connection, "swap_test_posts").unwrap();
+
+        for index in set_logged(&mut connection, "swap_test_posts").unwrap() {
+            build_index(&mut connection, &index).unwrap();
+        }
+
+        let swap = swap_tables(&mut connection, &["swap_test_posts"]).unwrap();
+
+        let foreign_keys: Vec<_> = swap
+            .foreign_keys
+            .iter()
+            .map(|foreign_key| {
+                (
+                    foreign_key.table_name.as_str(),
+                    foreign_key.constraint_name.as_str(),
+                    foreign_key.validated,
+                )
+            })
+            .collect();
+        assert_eq!(
+            foreign_keys,
+            [
+                ("swap_test_posts", "fk_swap_test_posts_owner_user_id", true),
+                ("swap_test_votes", "fk_swap_test_votes_post_id", false),
+            ]
+        );
+        assert!(check(
+            &mut connection,
+            "SELECT count(*) = 2 AS check FROM pg_constraint \
+             WHERE contype = 'f' AND NOT convalidated \
+             AND confrelid IN ('swap_test_users'::regclass, 'swap_test_posts'::regclass) \
+             AND conrelid IN ('swap_test_posts'::regclass, 'swap_test_votes'::regclass)"
+        ));
+    }
+}
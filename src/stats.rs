@@ -0,0 +1,56 @@
+use diesel::{
+    prelude::*,
+    sql_query,
+    sql_types::{BigInt, Integer, Nullable, Text},
+};
+
+use crate::checkpoint::{self, Checkpoint};
+
+#[derive(Debug, QueryableByName)]
+/// Represents the size and `Id` range of a loaded table.
+///
+/// # Fields
+///
+/// * `rows` - The number of rows of the table
+/// * `min_id` - The smallest `Id` of the table, if it has rows
+/// * `max_id` - The largest `Id` of the table, if it has rows
+/// * `total_size` - The size of the table and its indexes on disk, formatted by PostgreSQL
+pub(crate) struct TableStats {
+    #[diesel(sql_type = BigInt)]
+    pub rows: i64,
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub min_id: Option<i32>,
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub max_id: Option<i32>,
+    #[diesel(sql_type = Text)]
+    pub total_size: String,
+}
+
+/// Reads the statistics of a table.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `table_name` - The table to read the statistics of, one of `model::TABLE_NAMES`
+///
+/// # Returns
+///
+/// * `Ok((TableStats, Option<Checkpoint>))` - The statistics and the load checkpoint of the table
+/// * `Err(diesel::result::Error)` - If a query fails
+pub(crate) fn table_stats(
+    connection: &mut PgConnection,
+    table_name: &str,
+) -> QueryResult<(TableStats, Option<Checkpoint>)> {
+    // Table names cannot be bound, but only the names of our own tables are
+    // ever passed in.
+    let stats = sql_query(format!(
+        "SELECT count(*) AS rows, min(id) AS min_id, max(id) AS max_id, \
+         pg_size_pretty(pg_total_relation_size('{0}')) AS total_size FROM {0}",
+        table_name
+    ))
+    .get_result::<TableStats>(connection)?;
+
+    let checkpoint = checkpoint::load(connection, table_name)?;
+
+    Ok((stats, checkpoint))
+}
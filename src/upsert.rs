@@ -0,0 +1,174 @@
+use diesel::{
+    prelude::*,
+    sql_query,
+    sql_types::{Integer, Text},
+};
+
+#[derive(Debug, QueryableByName)]
+/// Represents the qualified name and the columns of a table.
+///
+/// # Fields
+///
+/// * `table_name` - The schema-qualified name of the table
+/// * `assignments` - `column = EXCLUDED.column` for every column but `id`
+struct MergeTarget {
+    #[diesel(sql_type = Text)]
+    table_name: String,
+    #[diesel(sql_type = Text)]
+    assignments: String,
+}
+
+#[derive(Debug, QueryableByName)]
+/// Represents the `Id` of a row written to a temporary copy.
+struct ShadowId {
+    #[diesel(sql_type = Integer)]
+    id: i32,
+}
+
+/// The table that rows are merged into once they are written.
+pub(crate) struct Shadow {
+    target: MergeTarget,
+    shadow_name: String,
+}
+
+/// Shadows a table with an empty temporary copy for the current transaction.
+///
+/// Temporary tables come first on the search path, so the models write
+/// their rows to the copy, from where `merge` moves them into the table.
+/// The copy has the defaults and check constraints of the table, so rows
+/// breaking them are refused when written, like without a copy. It is
+/// dropped when the transaction commits.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection, inside a transaction
+/// * `table_name` - The table to shadow
+///
+/// # Returns
+///
+/// * `Ok(Shadow)` - The table the rows are to be merged into
+/// * `Err(diesel::result::Error)` - If a statement fails
+pub(crate) fn shadow_table(connection: &mut PgConnection, table_name: &str) -> QueryResult<Shadow> {
+    // Resolve the table before the temporary copy hides it.
+    let target = sql_query(
+        "SELECT format('%I.%I', nspname, relname) AS table_name, \
+         (SELECT string_agg(format('%I = EXCLUDED.%I', attname, attname), ', ' ORDER BY attnum) \
+         FROM pg_attribute \
+         WHERE attrelid = pg_class.oid AND attnum > 0 AND NOT attisdropped AND attname <> 'id') \
+         AS assignments \
+         FROM pg_class \
+         JOIN pg_namespace ON pg_namespace.oid = pg_class.relnamespace \
+         WHERE pg_class.oid = to_regclass($1)",
+    )
+    .bind::<Text, _>(table_name)
+    .get_result::<MergeTarget>(connection)?;
+
+    sql_query(format!(
+        "CREATE TEMPORARY TABLE {} (LIKE {} INCLUDING DEFAULTS INCLUDING CONSTRAINTS) \
+         ON COMMIT DROP",
+        table_name, target.table_name
+    ))
+    .execute(connection)?;
+
+    Ok(Shadow {
+        target,
+        shadow_name: format!("pg_temp.{}", table_name),
+    })
+}
+
+/// Returns the `ON CONFLICT` action of a merge.
+///
+/// Skipped rows still lose the `removed_at` mark they got when they were
+/// missing from an earlier dump, since they are back.
+fn conflict_action(shadow: &Shadow, update: bool) -> String {
+    match update {
+        true => format!(
+            "DO UPDATE SET {} WHERE (existing.*) IS DISTINCT FROM (EXCLUDED.*)",
+            shadow.target.assignments
+        ),
+        false => {
+            "DO UPDATE SET removed_at = NULL WHERE existing.removed_at IS NOT NULL".to_string()
+        }
+    }
+}
+
+/// Moves the rows written to a temporary copy into the shadowed table.
+///
+/// Rows whose `Id` is already in the table are skipped, or update the
+/// existing row with `update`. Rows that did not change are left alone.
+/// Either way, rows coming back lose their `removed_at` mark.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection, inside the transaction of `shadow_table`
+/// * `shadow` - The shadowed table
+/// * `update` - Whether to update existing rows instead of skipping them
+///
+/// # Returns
+///
+/// * `Ok(usize)` - The number of rows inserted or updated
+/// * `Err(diesel::result::Error)` - If the statement fails
+pub(crate) fn merge(
+    connection: &mut PgConnection,
+    shadow: &Shadow,
+    update: bool,
+) -> QueryResult<usize> {
+    sql_query(format!(
+        "INSERT INTO {} AS existing SELECT * FROM {} ON CONFLICT (id) {}",
+        shadow.target.table_name,
+        shadow.shadow_name,
+        conflict_action(shadow, update)
+    ))
+    .execute(connection)
+}
+
+/// Moves a single row written to a temporary copy into the shadowed table,
+/// like `merge`.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection, inside the transaction of `shadow_table`
+/// * `shadow` - The shadowed table
+/// * `update` - Whether to update the existing row instead of skipping it
+/// * `id` - The `Id` of the row
+///
+/// # Returns
+///
+/// * `Ok(usize)` - The number of rows inserted or updated
+/// * `Err(diesel::result::Error)` - If the statement fails
+pub(crate) fn merge_row(
+    connection: &mut PgConnection,
+    shadow: &Shadow,
+    update: bool,
+    id: i32,
+) -> QueryResult<usize> {
+    sql_query(format!(
+        "INSERT INTO {} AS existing SELECT * FROM {} WHERE id = $1 ON CONFLICT (id) {}",
+        shadow.target.table_name,
+        shadow.shadow_name,
+        conflict_action(shadow, update)
+    ))
+    .bind::<Integer, _>(id)
+    .execute(connection)
+}
+
+/// Lists the `Id` of the rows written to a temporary copy.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection, inside the transaction of `shadow_table`
+/// * `shadow` - The shadowed table
+///
+/// # Returns
+///
+/// * `Ok(Vec<i32>)` - The `Id` of every row, in ascending order
+/// * `Err(diesel::result::Error)` - If the query fails
+pub(crate) fn shadow_ids(connection: &mut PgConnection, shadow: &Shadow) -> QueryResult<Vec<i32>> {
+    let ids = sql_query(format!(
+        "SELECT DISTINCT id FROM {} ORDER BY id",
+        shadow.shadow_name
+    ))
+    .load::<ShadowId>(connection)?;
+
+    Ok(ids.into_iter().map(|shadow_id| shadow_id.id).collect())
+}
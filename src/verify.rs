@@ -0,0 +1,449 @@
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    num::NonZeroUsize,
+    sync::Arc,
+};
+
+use diesel::{
+    pg::CopyFormat,
+    prelude::*,
+    sql_query,
+    sql_types::{BigInt, Integer, Nullable},
+};
+use tracing::warn;
+
+use crate::{
+    copy::CopyRow,
+    errors::{Context, EtlError},
+    model::{Loadable, ParallelXmlModelIterator, RowError, XmlModelIterator},
+    source::BytesRead,
+};
+
+/// The rows of an XML file or of its table, as compared by `verify`.
+///
+/// # Fields
+///
+/// * `rows` - The number of rows, including the rows of the XML file that cannot be parsed
+/// * `unparsed` - The number of rows of the XML file that cannot be parsed
+/// * `min_id` - The smallest `Id`, if there are rows
+/// * `max_id` - The largest `Id`, if there are rows
+/// * `checksums` - A checksum of every column, if they were computed
+#[derive(Debug, Default)]
+pub(crate) struct Summary {
+    pub rows: u64,
+    pub unparsed: u64,
+    pub min_id: Option<i32>,
+    pub max_id: Option<i32>,
+    pub checksums: Option<Vec<u64>>,
+}
+
+impl Summary {
+    /// Starts an empty summary.
+    ///
+    /// # Parameters
+    ///
+    /// * `columns` - The number of columns to compute a checksum of, if any
+    ///
+    /// # Returns
+    ///
+    /// * `Summary` - A summary without rows
+    fn new(columns: Option<usize>) -> Self {
+        Self {
+            checksums: columns.map(|columns| vec![0; columns]),
+            ..Self::default()
+        }
+    }
+
+    /// Adds the `Id` of a row to the range of the summary.
+    fn id(&mut self, id: i32) {
+        self.min_id = Some(self.min_id.map_or(id, |min_id| min_id.min(id)));
+        self.max_id = Some(self.max_id.map_or(id, |max_id| max_id.max(id)));
+    }
+
+    /// Adds the columns of a row to the checksums, if they are computed.
+    ///
+    /// Every column is hashed with the `Id` of its row, and the hashes are
+    /// summed, so the checksums do not depend on the order of the rows but
+    /// tell apart values swapped between rows.
+    ///
+    /// # Parameters
+    ///
+    /// * `fields` - The columns of the row in the `COPY` text format, the `Id` first
+    fn checksum<'a>(&mut self, mut fields: impl Iterator<Item = &'a [u8]>) {
+        let Some(checksums) = &mut self.checksums else {
+            return;
+        };
+
+        let Some(id) = fields.next() else {
+            return;
+        };
+
+        for (checksum, field) in checksums.iter_mut().zip(std::iter::once(id).chain(fields)) {
+            let mut hasher = DefaultHasher::new();
+            (id, field).hash(&mut hasher);
+            *checksum = checksum.wrapping_add(hasher.finish());
+        }
+    }
+}
+
+/// Splits a row of a `COPY` text stream into its columns.
+///
+/// # Parameters
+///
+/// * `line` - The row, with or without its line break
+///
+/// # Returns
+///
+/// * `impl Iterator<Item = &[u8]>` - The columns of the row, still escaped
+fn copy_fields(line: &[u8]) -> impl Iterator<Item = &[u8]> {
+    line.strip_suffix(b"\n")
+        .unwrap_or(line)
+        .split(|byte| *byte == b'\t')
+}
+
+#[derive(Debug, QueryableByName)]
+/// The number of rows and `Id` range of a table.
+struct IdRange {
+    #[diesel(sql_type = BigInt)]
+    rows: i64,
+    #[diesel(sql_type = Nullable<Integer>)]
+    min_id: Option<i32>,
+    #[diesel(sql_type = Nullable<Integer>)]
+    max_id: Option<i32>,
+}
+
+/// Reads every row of the XML file of the model `T`, without writing it.
+///
+/// # Parameters
+///
+/// * `file_path` - Path to the XML file, or `-` for standard input
+/// * `parse_threads` - The number of threads parsing the rows
+/// * `checksums` - Whether to compute the checksum of every column
+///
+/// # Returns
+///
+/// * `Ok(Summary)` - The rows of the file
+/// * `Err(EtlError)` - If the file cannot be read, or is not well-formed XML
+pub(crate) fn scan_file<T>(
+    file_path: &str,
+    parse_threads: NonZeroUsize,
+    checksums: bool,
+) -> Result<Summary, EtlError>
+where
+    T: Loadable + Send + 'static,
+{
+    let bytes = Arc::new(BytesRead::default());
+
+    let rows_iter: Box<dyn Iterator<Item = Result<T, RowError>>> = match parse_threads.get() {
+        1 => XmlModelIterator::<T>::new(file_path, bytes).map(|iter| Box::new(iter) as _),
+        threads => ParallelXmlModelIterator::<T>::new(file_path, threads, bytes)
+            .map(|iter| Box::new(iter) as _),
+    }
+    .context(|| format!("Error creating XML model iterator for {}", T::TABLE_NAME))?;
+
+    let mut summary = Summary::new(checksums.then_some(T::COLUMN_COUNT));
+    let mut copy_row = CopyRow::new(CopyFormat::Text);
+
+    for row in rows_iter {
+        match row {
+            Ok(row) => {
+                summary.id(row.id());
+                summary.checksum(copy_fields(copy_row.encode(&row)));
+            }
+            // A row that cannot be parsed is still a row of the file
+            Err(error) if error.row.is_some() => {
+                warn!(
+                    position = error.position,
+                    id = error.id,
+                    error = %error.error,
+                    "Found a row that cannot be parsed"
+                );
+                summary.unparsed += 1;
+            }
+            Err(error) => {
+                return Err(EtlError::Xml(error.error))
+                    .context(|| format!("Error reading XML at byte {}", error.position));
+            }
+        }
+
+        summary.rows += 1;
+    }
+
+    Ok(summary)
+}
+
+/// Reads the rows of the table of the model `T` that were not marked as
+/// removed.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `checksums` - Whether to compute the checksum of every column, reading every row
+///
+/// # Returns
+///
+/// * `Ok(Summary)` - The rows of the table
+/// * `Err(EtlError)` - If a query fails
+pub(crate) fn scan_table<T>(
+    connection: &mut PgConnection,
+    checksums: bool,
+) -> Result<Summary, EtlError>
+where
+    T: Loadable,
+{
+    // Table names cannot be bound, but only the names of our own tables are
+    // ever passed in.
+    let id_range = sql_query(format!(
+        "SELECT count(*) AS rows, min(id) AS min_id, max(id) AS max_id \
+         FROM {} WHERE removed_at IS NULL",
+        T::TABLE_NAME
+    ))
+    .get_result::<IdRange>(connection)?;
+
+    let mut summary = Summary {
+        rows: id_range.rows as u64,
+        min_id: id_range.min_id,
+        max_id: id_range.max_id,
+        ..Summary::new(checksums.then_some(T::COLUMN_COUNT))
+    };
+
+    if checksums {
+        T::copy_to(connection, CopyFormat::Text, |rows| {
+            let mut line = Vec::new();
+
+            while rows.read_until(b'\n', &mut line)? > 0 {
+                // `removed_at` comes last, and is not in the XML files
+                let mut fields: Vec<&[u8]> = copy_fields(&line).collect();
+
+                if fields.pop() == Some(b"\\N") {
+                    summary.checksum(fields.into_iter());
+                }
+
+                line.clear();
+            }
+
+            Ok(())
+        })?;
+    }
+
+    Ok(summary)
+}
+
+/// Lists how the rows of a table differ from those of its XML file.
+///
+/// # Parameters
+///
+/// * `file` - The rows of the XML file
+/// * `table` - The rows of the table
+/// * `column_names` - The columns the checksums are computed on
+///
+/// # Returns
+///
+/// * `Vec<String>` - The differences, empty if the table matches the file
+pub(crate) fn differences(file: &Summary, table: &Summary, column_names: &[&str]) -> Vec<String> {
+    let mut differences = Vec::new();
+
+    let id = |id: Option<i32>| id.map_or("none".to_string(), |id| id.to_string());
+
+    if file.unparsed > 0 {
+        differences.push(format!(
+            "{} of the rows of the XML file cannot be parsed",
+            file.unparsed
+        ));
+    }
+
+    if file.rows != table.rows {
+        differences.push(format!(
+            "{} rows in the XML file, {} in the table",
+            file.rows, table.rows
+        ));
+    }
+
+    if file.min_id != table.min_id {
+        differences.push(format!(
+            "smallest Id {} in the XML file, {} in the table",
+            id(file.min_id),
+            id(table.min_id)
+        ));
+    }
+
+    if file.max_id != table.max_id {
+        differences.push(format!(
+            "largest Id {} in the XML file, {} in the table",
+            id(file.max_id),
+            id(table.max_id)
+        ));
+    }
+
+    if let (Some(file_checksums), Some(table_checksums)) = (&file.checksums, &table.checksums) {
+        let columns: Vec<&str> = column_names
+            .iter()
+            .zip(file_checksums.iter().zip(table_checksums))
+            .filter(|(_, (file_checksum, table_checksum))| file_checksum != table_checksum)
+            .map(|(column, _)| *column)
+            .collect();
+
+        if !columns.is_empty() {
+            differences.push(format!("checksums of {} differ", columns.join(", ")));
+        }
+    }
+
+    differences
+}
+
+/// The comparison of a table with its XML file.
+///
+/// # Fields
+///
+/// * `file` - The rows of the XML file
+/// * `table` - The rows of the table
+/// * `differences` - How the table differs from the file, empty if it matches
+#[derive(Debug)]
+pub(crate) struct Verification {
+    pub file: Summary,
+    pub table: Summary,
+    pub differences: Vec<String>,
+}
+
+/// Compares the table of the model `T` with its XML file.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `file_path` - Path to the XML file, or `-` for standard input
+/// * `parse_threads` - The number of threads parsing the rows
+/// * `checksums` - Whether to compare a checksum of every column
+///
+/// # Returns
+///
+/// * `Ok(Verification)` - The comparison of the table with its file
+/// * `Err(EtlError)` - If the file or the table cannot be read
+pub(crate) fn verify_table<T>(
+    connection: &mut PgConnection,
+    file_path: &str,
+    parse_threads: NonZeroUsize,
+    checksums: bool,
+) -> Result<Verification, EtlError>
+where
+    T: Loadable + Send + 'static,
+{
+    let file = scan_file::<T>(file_path, parse_threads, checksums)?;
+    let table = scan_table::<T>(connection, checksums)?;
+    let differences = differences(&file, &table, T::COLUMN_NAMES);
+
+    Ok(Verification {
+        file,
+        table,
+        differences,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Summary, copy_fields, differences};
+
+    const COLUMN_NAMES: &[&str] = &["id", "name", "score"];
+
+    fn summary(rows: u64, ids: Option<(i32, i32)>, checksums: Option<Vec<u64>>) -> Summary {
+        Summary {
+            rows,
+            unparsed: 0,
+            min_id: ids.map(|(min_id, _)| min_id),
+            max_id: ids.map(|(_, max_id)| max_id),
+            checksums,
+        }
+    }
+
+    #[test]
+    fn differences_are_empty_for_matching_summaries() {
+        let file = summary(3, Some((1, 5)), Some(vec![1, 2, 3]));
+        let table = summary(3, Some((1, 5)), Some(vec![1, 2, 3]));
+
+        assert!(differences(&file, &table, COLUMN_NAMES).is_empty());
+    }
+
+    #[test]
+    fn differences_list_the_counts_and_ids() {
+        let file = Summary {
+            unparsed: 1,
+            ..summary(3, Some((1, 5)), None)
+        };
+        let table = summary(2, Some((2, 5)), None);
+
+        assert_eq!(
+            differences(&file, &table, COLUMN_NAMES),
+            [
+                "1 of the rows of the XML file cannot be parsed",
+                "3 rows in the XML file, 2 in the table",
+                "smallest Id 1 in the XML file, 2 in the table",
+            ]
+        );
+    }
+
+    #[test]
+    fn differences_show_a_missing_range_as_none() {
+        let file = summary(2, Some((1, 9)), None);
+        let table = summary(0, None, None);
+
+        assert_eq!(
+            differences(&file, &table, COLUMN_NAMES),
+            [
+                "2 rows in the XML file, 0 in the table",
+                "smallest Id 1 in the XML file, none in the table",
+                "largest Id 9 in the XML file, none in the table",
+            ]
+        );
+    }
+
+    #[test]
+    fn differences_name_the_columns_whose_checksums_differ() {
+        let file = summary(3, Some((1, 5)), Some(vec![1, 2, 3]));
+        let table = summary(3, Some((1, 5)), Some(vec![1, 7, 8]));
+
+        assert_eq!(
+            differences(&file, &table, COLUMN_NAMES),
+            ["checksums of name, score differ"]
+        );
+    }
+
+    #[test]
+    fn differences_skip_checksums_computed_on_one_side_only() {
+        let file = summary(3, Some((1, 5)), Some(vec![1, 2, 3]));
+        let table = summary(3, Some((1, 5)), None);
+
+        assert!(differences(&file, &table, COLUMN_NAMES).is_empty());
+    }
+
+    #[test]
+    fn checksums_do_not_depend_on_the_order_of_the_rows() {
+        let rows = [b"1\ta\t10\n".as_slice(), b"2\tb\t20\n", b"3\t\\N\t30\n"];
+        let mut ordered = Summary::new(Some(3));
+        let mut reversed = Summary::new(Some(3));
+
+        for row in rows {
+            ordered.checksum(copy_fields(row));
+        }
+
+        for row in rows.iter().rev() {
+            reversed.checksum(copy_fields(row));
+        }
+
+        assert_eq!(ordered.checksums, reversed.checksums);
+    }
+
+    #[test]
+    fn checksums_tell_apart_values_swapped_between_rows() {
+        let mut file = Summary::new(Some(2));
+        let mut table = Summary::new(Some(2));
+
+        file.checksum(copy_fields(b"1\ta\n"));
+        file.checksum(copy_fields(b"2\tb\n"));
+        table.checksum(copy_fields(b"1\tb\n"));
+        table.checksum(copy_fields(b"2\ta\n"));
+
+        assert_eq!(
+            differences(&file, &table, &["id", "name"]),
+            ["checksums of name differ"]
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use diesel::{
+    prelude::*,
+    sql_query,
+    sql_types::{Bool, Text},
+};
+
+#[derive(Debug, QueryableByName)]
+/// Represents a materialized view of the database, such as `post_stats`.
+///
+/// # Fields
+///
+/// * `view_name` - The name of the view, qualified if it is not on the search path
+/// * `concurrently` - Whether the view can be refreshed with `REFRESH MATERIALIZED VIEW CONCURRENTLY`
+pub(crate) struct MaterializedView {
+    #[diesel(sql_type = Text)]
+    pub view_name: String,
+    #[diesel(sql_type = Bool)]
+    pub concurrently: bool,
+}
+
+/// Lists the materialized views of the schemas on the search path.
+///
+/// A view can be refreshed concurrently once it is populated, if it has a
+/// unique index on plain columns without a `WHERE` clause, like
+/// `idx_post_stats_id`.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+///
+/// # Returns
+///
+/// * `Ok(Vec<MaterializedView>)` - The materialized views, by name
+/// * `Err(diesel::result::Error)` - If the query fails
+pub(crate) fn materialized_views(
+    connection: &mut PgConnection,
+) -> QueryResult<Vec<MaterializedView>> {
+    sql_query(
+        "SELECT view_class.oid::regclass::text AS view_name, \
+         view_class.relispopulated AND EXISTS ( \
+         SELECT 1 FROM pg_index \
+         WHERE indrelid = view_class.oid AND indisunique AND indisvalid \
+         AND indexprs IS NULL AND indpred IS NULL) AS concurrently \
+         FROM pg_class AS view_class \
+         JOIN pg_namespace ON pg_namespace.oid = view_class.relnamespace \
+         WHERE view_class.relkind = 'm' AND nspname = ANY(current_schemas(false)) \
+         ORDER BY view_name",
+    )
+    .load(connection)
+}
+
+/// Refreshes a materialized view with the current rows of its tables.
+///
+/// # Parameters
+///
+/// * `connection` - A mutable reference to the PostgreSQL database connection
+/// * `view` - The view to refresh
+///
+/// # Returns
+///
+/// * `Ok(())` - If the view was refreshed
+/// * `Err(diesel::result::Error)` - If the refresh fails
+pub(crate) fn refresh(connection: &mut PgConnection, view: &MaterializedView) -> QueryResult<()> {
+    // A concurrent refresh does not lock out the readers of the view.
+    let concurrently = match view.concurrently {
+        true => " CONCURRENTLY",
+        false => "",
+    };
+
+    sql_query(format!(
+        "REFRESH MATERIALIZED VIEW{} {}",
+        concurrently, view.view_name
+    ))
+    .execute(connection)?;
+
+    Ok(())
+}